@@ -1,7 +1,29 @@
-use clap::Parser;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::layer::{read_layers, LayerSpec};
+use crate::layout::Layout;
+use crate::painter::order::Order;
+use crate::painter::painter::PainterConfig;
+use crate::pix::client::{
+    ClientConfig, FlushPolicy, Timeouts, DEFAULT_BUFFER_SIZE, DEFAULT_CONNECT_TIMEOUT,
+    DEFAULT_READ_TIMEOUT, DEFAULT_UDP_MTU, DEFAULT_WRITE_TIMEOUT, MIN_UDP_MTU,
+};
+use crate::pix::limit::{RateLimitConfig, RateUnit};
+use crate::pix::reconnect::ReconnectConfig;
+use crate::playback::{FrameRange, OnEnd, Playback, PlaybackConfig};
+use crate::scale::{Align, Filter, Scale};
 
 #[derive(Parser)]
-#[command(author, version, about, disable_help_flag = true)]
+#[command(
+    author,
+    version,
+    about,
+    disable_help_flag = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Arguments {
     // manually redefine help, but without short option, because `-h`
     // is already used by the height option.
@@ -9,20 +31,40 @@ pub struct Arguments {
     #[clap(long, action = clap::ArgAction::HelpLong)]
     help: Option<bool>,
 
-    /// The host to pwn "host:port"
-    host: String,
+    /// Show more log messages, repeat for even more
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Show fewer log messages, repeat for even fewer
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "verbose")]
+    quiet: u8,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The host to pwn "host:port", prefix with "udp://" to use UDP
+    #[arg(required = true)]
+    host: Option<String>,
 
     /// Image path(s)
     #[arg(
         short,
         long,
         value_name = "PATH",
-        required = true,
+        required_unless_present_any = ["layer", "layers"],
         alias = "images",
         num_args(1..)
     )]
     image: Vec<String>,
 
+    /// Add a layer on top, as "image=PATH,x=X,y=Y,w=WIDTH,h=HEIGHT,fps=RATE,scale=SCALE,align=ALIGN,filter=FILTER,playback=PLAYBACK,loops=COUNT,frames=RANGE" [default size: image size, keeping its aspect ratio if only w or h is set]
+    #[arg(long, value_name = "LAYER")]
+    layer: Vec<LayerSpec>,
+
+    /// Add layers on top from a file, with a layer on each line
+    #[arg(long, value_name = "PATH")]
+    layers: Option<String>,
+
     /// Draw width [default: screen width]
     #[arg(short, long, value_name = "PIXELS")]
     width: Option<u16>,
@@ -30,6 +72,18 @@ pub struct Arguments {
     #[arg(short, long, value_name = "PIXELS")]
     height: Option<u16>,
 
+    /// How to scale the image to the draw size
+    #[arg(long, value_enum, value_name = "SCALE", default_value_t = Scale::Stretch)]
+    scale: Scale,
+
+    /// Where to place the scaled image within the draw size
+    #[arg(long, value_enum, value_name = "ALIGN", default_value_t = Align::Center)]
+    align: Align,
+
+    /// The filter to resample the image with when scaling
+    #[arg(long, value_enum, value_name = "FILTER", default_value_t = Filter::Gaussian)]
+    filter: Filter,
+
     /// Draw X offset
     #[arg(
         short,
@@ -55,13 +109,226 @@ pub struct Arguments {
     #[arg(short = 'r', long, value_name = "RATE", default_value_t = 1)]
     fps: u32,
 
+    /// Speed to play animations at, 2 plays twice as fast
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f64,
+
+    /// Order to play the frames of animations in
+    #[arg(long, value_enum, value_name = "PLAYBACK", default_value_t = Playback::Forward)]
+    playback: Playback,
+
+    /// Number of times to play animations, 0 plays forever [default: loop count of the image, or forever]
+    #[arg(long, value_name = "COUNT")]
+    loops: Option<u32>,
+
+    /// Range of frames to play, as "START-END", counting from 0 [default: all frames]
+    #[arg(long, value_name = "RANGE")]
+    frames: Option<FrameRange>,
+
+    /// What to do once all animations finished playing
+    #[arg(long, value_enum, value_name = "ACTION", default_value_t = OnEnd::Hold)]
+    on_end: OnEnd,
+
+    /// Paint all pixels every N passes with animations, only changed pixels in between
+    #[arg(long, value_name = "PASSES", default_value_t = 1)]
+    refresh: usize,
+
+    /// Layout to divide the canvas over painter threads with
+    #[arg(long, value_enum, value_name = "LAYOUT", default_value_t = Layout::Vertical)]
+    layout: Layout,
+
+    /// Chunk size with the steal layout
+    #[arg(long, value_name = "PIXELS", default_value_t = 32)]
+    chunk_size: u16,
+
+    /// Order to paint the pixels of each painter area in
+    #[arg(long, value_enum, value_name = "ORDER", default_value_t = Order::Columns)]
+    order: Order,
+
+    /// Seed for the random paint order
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Read back the canvas, and repaint overwritten pixels first [default: off]
+    #[arg(long)]
+    defend: bool,
+
     /// Use binary mode to set pixels (`PB` protocol extension) [default: off]
     #[arg(short, long, alias = "bin")]
     binary: bool,
 
-    /// Flush socket after each pixel [default: true]
+    /// Flush socket after each batch of pixels, unless flushing by count, size or interval [default: true]
     #[arg(short, long, action = clap::ArgAction::Set, value_name = "ENABLED", default_value_t = true)]
     flush: bool,
+
+    /// Flush socket after this many pixel commands [default: off]
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..))]
+    flush_commands: Option<u64>,
+
+    /// Flush socket after this many bytes [default: off]
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    flush_bytes: Option<u64>,
+
+    /// Flush socket when this much time passed since the last flush [default: off]
+    #[arg(long, value_name = "MILLIS")]
+    flush_interval: Option<u64>,
+
+    /// Limit the rate over all connections, in units per second [default: off]
+    #[arg(long, value_name = "RATE", value_parser = clap::value_parser!(u64).range(1..))]
+    rate_limit: Option<u64>,
+
+    /// Limit the rate of each connection, in units per second [default: off]
+    #[arg(long, value_name = "RATE", value_parser = clap::value_parser!(u64).range(1..))]
+    connection_rate_limit: Option<u64>,
+
+    /// The unit of rate limits
+    #[arg(long, value_enum, value_name = "UNIT", default_value_t = RateUnit::Pixels)]
+    rate_unit: RateUnit,
+
+    /// Size of the socket write buffer
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_BUFFER_SIZE)]
+    buffer_size: usize,
+
+    /// Use UDP to send pixels, instead of TCP [default: off]
+    #[arg(short, long)]
+    udp: bool,
+
+    /// Maximum UDP datagram payload size
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_UDP_MTU, value_parser = parse_mtu)]
+    mtu: usize,
+
+    /// Delay before reconnecting, doubled after each failed retry
+    #[arg(long, value_name = "MILLIS", default_value_t = 500)]
+    reconnect_delay: u64,
+
+    /// Maximum delay before reconnecting
+    #[arg(long, value_name = "MILLIS", default_value_t = 30000)]
+    reconnect_max_delay: u64,
+
+    /// Quit after this many failed retries in a row [default: unlimited]
+    #[arg(long, value_name = "COUNT")]
+    max_retries: Option<u32>,
+
+    /// Timeout for connecting, 0 to wait forever
+    #[arg(long, value_name = "MILLIS", default_value_t = DEFAULT_CONNECT_TIMEOUT)]
+    connect_timeout: u64,
+
+    /// Timeout for reading a reply from the server, 0 to wait forever
+    #[arg(long, value_name = "MILLIS", default_value_t = DEFAULT_READ_TIMEOUT)]
+    read_timeout: u64,
+
+    /// Timeout for writing to the server, 0 to wait forever
+    #[arg(long, value_name = "MILLIS", default_value_t = DEFAULT_WRITE_TIMEOUT)]
+    write_timeout: u64,
+
+    /// Report throughput statistics every this many seconds [default: off]
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    stats: Option<u64>,
+
+    /// Report statistics as JSON lines, every second unless set with --stats [default: off]
+    #[arg(long)]
+    stats_json: bool,
+}
+
+/// A subcommand to run instead of pixelflutting.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a local pixelflut server, to benchmark throughput
+    #[command(disable_help_flag = true)]
+    Serve(ServeArguments),
+
+    /// Grab a snapshot of the canvas, and save it as PNG
+    #[command(disable_help_flag = true)]
+    Grab(GrabArguments),
+}
+
+#[derive(Args)]
+pub struct ServeArguments {
+    /// Show this help
+    #[clap(long, action = clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+
+    /// The host to listen on "host:port"
+    #[arg(default_value = "0.0.0.0:1337")]
+    host: String,
+
+    /// Canvas width
+    #[arg(short, long, value_name = "PIXELS", default_value_t = 800)]
+    width: u16,
+    /// Canvas height
+    #[arg(short, long, value_name = "PIXELS", default_value_t = 600)]
+    height: u16,
+}
+
+impl ServeArguments {
+    /// Get the host to listen on.
+    pub fn host(&self) -> &str {
+        self.host.as_str()
+    }
+
+    /// Get the canvas size.
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+}
+
+#[derive(Args)]
+pub struct GrabArguments {
+    /// Show this help
+    #[clap(long, action = clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+
+    /// The host to grab from "host:port"
+    host: String,
+
+    /// Output PNG path
+    #[arg(short, long, value_name = "PATH")]
+    output: String,
+
+    /// Grab width [default: screen width]
+    #[arg(short, long, value_name = "PIXELS")]
+    width: Option<u16>,
+    /// Grab height [default: screen height]
+    #[arg(short, long, value_name = "PIXELS")]
+    height: Option<u16>,
+
+    /// Grab X offset
+    #[arg(short, value_name = "PIXELS", default_value_t = 0)]
+    x: u16,
+    /// Grab Y offset
+    #[arg(short, value_name = "PIXELS", default_value_t = 0)]
+    y: u16,
+
+    /// Number of concurrent connections [default: number of CPUs]
+    #[arg(short, long)]
+    count: Option<usize>,
+}
+
+impl GrabArguments {
+    /// Get the host to grab from.
+    pub fn host(&self) -> &str {
+        self.host.as_str()
+    }
+
+    /// Get the output path.
+    pub fn output(&self) -> &str {
+        self.output.as_str()
+    }
+
+    /// Get the grab size.
+    pub fn size(&self) -> (Option<u16>, Option<u16>) {
+        (self.width, self.height)
+    }
+
+    /// Get the grab offset.
+    pub fn offset(&self) -> (u16, u16) {
+        (self.x, self.y)
+    }
+
+    /// Get the connection count.
+    pub fn count(&self) -> usize {
+        self.count.unwrap_or_else(num_cpus::get)
+    }
 }
 
 /// CLI argument handler.
@@ -76,9 +343,22 @@ impl ArgHandler {
         }
     }
 
+    /// Get the subcommand to run, if any.
+    pub fn command(&self) -> Option<&Command> {
+        self.data.command.as_ref()
+    }
+
+    /// Get the log verbosity, positive for more and negative for fewer messages.
+    pub fn verbosity(&self) -> i8 {
+        self.data.verbose.min(i8::MAX as u8) as i8 - self.data.quiet.min(i8::MAX as u8) as i8
+    }
+
     /// Get the host property.
     pub fn host(&self) -> &str {
-        self.data.host.as_str()
+        self.data
+            .host
+            .as_deref()
+            .expect("host is required without subcommand")
     }
 
     /// Get the thread count.
@@ -91,12 +371,39 @@ impl ArgHandler {
         self.data.image.iter().map(|x| x.as_str()).collect()
     }
 
+    /// Get the layers to add on top of the image.
+    ///
+    /// Layers from a file come first, then the layers given on the command line.
+    pub fn layers(&self) -> Result<Vec<LayerSpec>, String> {
+        let mut layers = match &self.data.layers {
+            Some(path) => read_layers(path)?,
+            None => Vec::new(),
+        };
+        layers.extend(self.data.layer.iter().cloned());
+        Ok(layers)
+    }
+
     /// Get the image size.
     /// Use the given default value if not set.
     pub fn size(&self) -> (Option<u16>, Option<u16>) {
         (self.data.width, self.data.height)
     }
 
+    /// Get how to scale the image.
+    pub fn scale(&self) -> Scale {
+        self.data.scale
+    }
+
+    /// Get where to place the scaled image.
+    pub fn align(&self) -> Align {
+        self.data.align
+    }
+
+    /// Get the filter to resample the image with.
+    pub fn filter(&self) -> Filter {
+        self.data.filter
+    }
+
     /// Get the image offset.
     pub fn offset(&self) -> (i16, i16) {
         (self.data.x, self.data.y)
@@ -107,13 +414,169 @@ impl ArgHandler {
         self.data.fps
     }
 
+    /// Get the speed to play animations at.
+    pub fn speed(&self) -> f64 {
+        self.data.speed
+    }
+
+    /// Get the configuration for playing animations.
+    pub fn playback_config(&self) -> PlaybackConfig {
+        PlaybackConfig {
+            playback: self.data.playback,
+            loops: self.data.loops,
+            frames: self.data.frames.unwrap_or_default(),
+        }
+    }
+
+    /// Get what to do once all animations finished playing.
+    pub fn on_end(&self) -> OnEnd {
+        self.data.on_end
+    }
+
+    /// Get the number of passes between full refreshes.
+    pub fn refresh(&self) -> usize {
+        self.data.refresh
+    }
+
+    /// Get the painter layout.
+    pub fn layout(&self) -> Layout {
+        self.data.layout
+    }
+
+    /// Get the chunk size for the steal layout.
+    pub fn chunk_size(&self) -> u16 {
+        self.data.chunk_size
+    }
+
+    /// Get the paint order.
+    pub fn order(&self) -> Order {
+        self.data.order
+    }
+
+    /// Get the seed for random paint orders.
+    pub fn seed(&self) -> u64 {
+        self.data.seed
+    }
+
+    /// Get the configuration for painters.
+    pub fn painter_config(&self) -> PainterConfig {
+        PainterConfig {
+            refresh: self.refresh(),
+            order: self.order(),
+            seed: self.seed(),
+            layout: self.layout(),
+            chunk_size: self.chunk_size(),
+            defend: self.defend(),
+        }
+    }
+
+    /// Whether to defend the image against overwrites.
+    pub fn defend(&self) -> bool {
+        self.data.defend
+    }
+
     /// Whether to use binary mode.
     pub fn binary(&self) -> bool {
         self.data.binary
     }
 
-    /// Whether to flush after each pixel.
-    pub fn flush(&self) -> bool {
-        self.data.flush
+    /// Get the policy for when to flush.
+    pub fn flush_policy(&self) -> FlushPolicy {
+        FlushPolicy {
+            batch: self.data.flush,
+            commands: self.data.flush_commands,
+            bytes: self.data.flush_bytes,
+            interval: self.data.flush_interval.map(Duration::from_millis),
+        }
+    }
+
+    /// Get the rate limits.
+    pub fn rate_limit(&self) -> RateLimitConfig {
+        RateLimitConfig {
+            global: self.data.rate_limit,
+            connection: self.data.connection_rate_limit,
+            unit: self.data.rate_unit,
+        }
+    }
+
+    /// Get the size of the socket write buffer.
+    pub fn buffer_size(&self) -> usize {
+        self.data.buffer_size
+    }
+
+    /// Whether to use UDP.
+    pub fn udp(&self) -> bool {
+        self.data.udp
+    }
+
+    /// Get the maximum UDP datagram payload size.
+    pub fn mtu(&self) -> usize {
+        self.data.mtu
+    }
+
+    /// Get the interval to report statistics at, if reporting.
+    pub fn stats_interval(&self) -> Option<Duration> {
+        match (self.data.stats, self.data.stats_json) {
+            (Some(secs), _) => Some(Duration::from_secs(secs)),
+            (None, true) => Some(Duration::from_secs(1)),
+            (None, false) => None,
+        }
+    }
+
+    /// Whether to report statistics as JSON lines.
+    pub fn stats_json(&self) -> bool {
+        self.data.stats_json
+    }
+
+    /// Get the policy for reconnecting.
+    pub fn reconnect_config(&self) -> ReconnectConfig {
+        ReconnectConfig {
+            delay: Duration::from_millis(self.data.reconnect_delay),
+            max_delay: Duration::from_millis(self.data.reconnect_max_delay),
+            max_retries: self.data.max_retries,
+        }
+    }
+
+    /// Get the timeouts for connections, disabled timeouts are `None`.
+    pub fn timeouts(&self) -> Timeouts {
+        let millis = |millis: u64| (millis > 0).then(|| Duration::from_millis(millis));
+        Timeouts {
+            connect: millis(self.data.connect_timeout),
+            read: millis(self.data.read_timeout),
+            write: millis(self.data.write_timeout),
+        }
+    }
+
+    /// Get the configuration for pixelflut clients.
+    pub fn client_config(&self) -> ClientConfig {
+        ClientConfig {
+            binary: self.binary(),
+            flush: self.flush_policy(),
+            buffer_size: self.buffer_size(),
+            udp: self.udp(),
+            mtu: self.mtu(),
+            reconnect: self.reconnect_config(),
+            timeouts: self.timeouts(),
+            rate_limit: self.rate_limit(),
+        }
+    }
+}
+
+/// Parse a UDP datagram payload size, which must fit a single pixel command.
+fn parse_mtu(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(mtu) if mtu >= MIN_UDP_MTU => Ok(mtu),
+        _ => Err(format!(
+            "'{}' is not a size of at least {} bytes",
+            value, MIN_UDP_MTU
+        )),
+    }
+}
+
+/// Parse an animation speed, which must be positive.
+fn parse_speed(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("'{}' is not a positive number", value)),
     }
 }
@@ -22,4 +22,86 @@ impl Color {
     pub fn as_hex(self) -> String {
         format!("{:02X}{:02X}{:02X}{:02X}", self.r, self.g, self.b, self.a)
     }
+
+    /// Get a hexadecimal representation of the color without alpha channel,
+    /// such as `ffffff` for white and `ff0000` for red.
+    pub fn as_rgb_hex(self) -> String {
+        format!("{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// Parse a color from a hexadecimal representation.
+    ///
+    /// Both the `rrggbb` and `rrggbbaa` forms are supported.
+    /// `None` is returned if the given value is malformed.
+    pub fn from_hex(hex: &str) -> Option<Color> {
+        // Only allow digits, parsing channels would accept a sign
+        if !matches!(hex.len(), 6 | 8) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Color::from(
+            channel(0)?,
+            channel(2)?,
+            channel(4)?,
+            if hex.len() == 8 { channel(6)? } else { 255 },
+        ))
+    }
+
+    /// Blend the given color on top of this opaque color, using its alpha channel.
+    pub fn blend(self, top: Color) -> Color {
+        let mix = |below: u8, above: u8| {
+            ((above as u32 * top.a as u32 + below as u32 * (255 - top.a as u32)) / 255) as u8
+        };
+        Color::from(
+            mix(self.r, top.r),
+            mix(self.g, top.g),
+            mix(self.b, top.b),
+            255,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a color from hex, as its channels.
+    fn channels(hex: &str) -> Option<(u8, u8, u8, u8)> {
+        Color::from_hex(hex).map(|color| (color.r, color.g, color.b, color.a))
+    }
+
+    #[test]
+    fn parse_hex() {
+        assert_eq!(channels("ff8000"), Some((255, 128, 0, 255)));
+        assert_eq!(channels("FF8000"), Some((255, 128, 0, 255)));
+        assert_eq!(channels("0a0b0c80"), Some((10, 11, 12, 128)));
+        assert_eq!(channels("00000000"), Some((0, 0, 0, 0)));
+    }
+
+    #[test]
+    fn parse_invalid_hex() {
+        for hex in [
+            "",
+            "fff",
+            "fffff",
+            "fffffff",
+            "fffffffff",
+            "gg0000",
+            "+f0000",
+            "-f0000",
+            "ff 000",
+            "ffé00",
+        ] {
+            assert!(channels(hex).is_none(), "{:?}", hex);
+        }
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let color = Color::from(1, 2, 254, 255);
+        assert_eq!(color.as_hex(), "0102FEFF");
+        assert_eq!(color.as_rgb_hex(), "0102fe");
+        assert_eq!(channels(&color.as_hex()), Some((1, 2, 254, 255)));
+    }
 }
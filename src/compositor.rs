@@ -0,0 +1,245 @@
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use image::{imageops, RgbaImage};
+use log::info;
+
+use crate::image_manager::ImageManager;
+use crate::layer::LayerSpec;
+use crate::logger::warn_limited;
+use crate::pix::canvas::Canvas;
+use crate::playback::OnEnd;
+
+/// A layer being painted.
+struct Layer {
+    /// The frames of the layer.
+    images: ImageManager,
+
+    /// The position of the layer on the canvas.
+    position: (i64, i64),
+
+    /// Frames per second, for frames without their own delay.
+    fps: u32,
+}
+
+impl Layer {
+    /// The time to show the current frame for, when played at the given speed.
+    fn delay(&self, speed: f64) -> Duration {
+        let delay = self
+            .images
+            .frame_delay()
+            .unwrap_or_else(|| Duration::from_secs_f64(1.0 / self.fps.max(1) as f64));
+        delay.div_f64(speed)
+    }
+}
+
+/// A compositor, stacking the frames of multiple layers onto a single canvas.
+///
+/// Layers are stacked in order, the last layer is on top. Each layer walks
+/// through its frames at its own pace.
+pub struct Compositor {
+    layers: Vec<Layer>,
+
+    /// The position of the canvas on the screen, covering all layers.
+    offset: (i16, i16),
+
+    /// The size of the canvas, covering all layers.
+    size: (u16, u16),
+
+    /// The speed to play animations at, relative to their own frame rate.
+    speed: f64,
+
+    /// What to do once all animations finished playing.
+    on_end: OnEnd,
+}
+
+impl Compositor {
+    /// Load the images of all layers.
+    ///
+    /// Layers without their own frame rate use the given one.
+    /// Animations are played at the given speed.
+    /// There must be at least one layer.
+    pub fn load(specs: &[LayerSpec], fps: u32, speed: f64, on_end: OnEnd) -> Compositor {
+        assert!(!specs.is_empty(), "No layers to paint");
+        let layers: Vec<(ImageManager, &LayerSpec)> = specs
+            .iter()
+            .map(|spec| {
+                let paths: Vec<&str> = spec.images.iter().map(|path| path.as_str()).collect();
+                (
+                    ImageManager::load(
+                        &paths,
+                        spec.size,
+                        spec.scale,
+                        spec.align,
+                        spec.filter,
+                        spec.playback,
+                    ),
+                    spec,
+                )
+            })
+            .collect();
+
+        // Determine the bounds of all layers on the screen, covering all their frames
+        let bounds = |(images, spec): &(ImageManager, &LayerSpec)| {
+            let (w, h) = images.size();
+            let (x, y) = (spec.offset.0 as i64, spec.offset.1 as i64);
+            (x, y, x + w as i64, y + h as i64)
+        };
+        let (x0, y0, x1, y1) = layers.iter().map(bounds).fold(
+            (i64::MAX, i64::MAX, i64::MIN, i64::MIN),
+            |(x0, y0, x1, y1), (lx0, ly0, lx1, ly1)| {
+                (x0.min(lx0), y0.min(ly0), x1.max(lx1), y1.max(ly1))
+            },
+        );
+
+        Compositor {
+            layers: layers
+                .into_iter()
+                .map(|(images, spec)| Layer {
+                    images,
+                    position: (spec.offset.0 as i64 - x0, spec.offset.1 as i64 - y0),
+                    fps: spec.fps.unwrap_or(fps),
+                })
+                .collect(),
+            offset: (x0 as i16, y0 as i16),
+            size: (
+                (x1 - x0).min(u16::MAX as i64) as u16,
+                (y1 - y0).min(u16::MAX as i64) as u16,
+            ),
+            speed,
+            on_end,
+        }
+    }
+
+    /// Get the position of the canvas on the screen.
+    pub fn offset(&self) -> (i16, i16) {
+        self.offset
+    }
+
+    /// Get the size of the canvas.
+    pub fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    /// Stack the current frames of all layers into a single frame.
+    ///
+    /// Frames are shared with all painters, so a single layer is copied only
+    /// if its frame is smaller than the canvas.
+    fn compose(&self) -> Arc<RgbaImage> {
+        // A single layer with a frame covering the whole canvas is used as is
+        if let [layer] = self.layers.as_slice() {
+            let frame = layer.images.frame();
+            if frame.dimensions() == (self.size.0 as u32, self.size.1 as u32) {
+                return frame.clone();
+            }
+        }
+
+        let mut image = RgbaImage::new(self.size.0 as u32, self.size.1 as u32);
+        for layer in &self.layers {
+            imageops::overlay(
+                &mut image,
+                layer.images.frame().as_ref(),
+                layer.position.0,
+                layer.position.1,
+            );
+        }
+        Arc::new(image)
+    }
+
+    /// Start working in the compositor.
+    ///
+    /// This will walk through the frames of all layers, and pushes the stacked
+    /// image to all painters whenever a frame changes.
+    /// Frames are shown at fixed deadlines, so playback doesn't drift. When
+    /// falling behind, frames are skipped to catch up.
+    /// Returns when a stop signal is received, or once all animations finished
+    /// playing if set to exit then.
+    pub fn work(&mut self, canvas: &mut Canvas, stop: &Receiver<()>) {
+        let speed = self.speed;
+        let stats = canvas.stats();
+        let mut dropped = 0;
+
+        // Determine when to show the next frame of each animated layer
+        let now = Instant::now();
+        let mut due: Vec<Option<Instant>> = self
+            .layers
+            .iter()
+            .map(|layer| (layer.images.frame_count() > 1).then(|| now + layer.delay(speed)))
+            .collect();
+        canvas.update_image(&self.compose());
+
+        loop {
+            // Sleep until we need to show the next frame, or until stopped
+            let result = match due.iter().flatten().min() {
+                Some(next) => stop.recv_timeout(next.saturating_duration_since(Instant::now())),
+                None => stop.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match result {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            // Advance all layers that are due, skipping frames that are overdue
+            let now = Instant::now();
+            let mut skipped = 0;
+            let mut changed = false;
+            for (layer, due) in self.layers.iter_mut().zip(&mut due) {
+                let Some(next) = due else {
+                    continue;
+                };
+                let mut frames = 0;
+                let mut finished = false;
+                while *next <= now {
+                    // Keep the last frame once the layer finished playing
+                    if !layer.images.advance() {
+                        finished = true;
+                        break;
+                    }
+                    *next += layer.delay(speed);
+                    frames += 1;
+
+                    // Start over from now when more than a whole loop behind
+                    if frames > layer.images.frame_count() {
+                        *next = now + layer.delay(speed);
+                        break;
+                    }
+                }
+                skipped += frames.saturating_sub(1);
+                changed |= frames > 0;
+                if finished {
+                    *due = None;
+                }
+            }
+            if skipped > 0 {
+                warn_limited!(
+                    "skipped-frames",
+                    "Can't keep up with the frame rate, skipped {} frame(s)",
+                    skipped
+                );
+            }
+
+            // Update the image on the canvas
+            if changed {
+                canvas.update_image(&self.compose());
+            }
+
+            // Report painters that can't paint frames before the next one is shown
+            let total: u64 = stats.iter().map(|stats| stats.snapshot().dropped).sum();
+            if total > dropped {
+                warn_limited!(
+                    "dropped-frames",
+                    "Painters can't keep up with the frame rate, {} frame(s) were replaced before being painted completely",
+                    total - dropped
+                );
+                dropped = total;
+            }
+
+            // Stop once all animations finished playing, if asked to
+            if self.on_end == OnEnd::Exit && due.iter().all(Option::is_none) {
+                info!("All animations finished playing, stopping...");
+                return;
+            }
+        }
+    }
+}
@@ -1,103 +1,171 @@
 use image::codecs::gif::GifDecoder;
 use image::codecs::webp::WebPDecoder;
+use log::info;
 use rayon::prelude::*;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
-use std::thread::sleep;
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::pix::canvas::Canvas;
-use image::imageops::FilterType;
-use image::{AnimationDecoder, DynamicImage};
+use image::{AnimationDecoder, Delay, DynamicImage, RgbaImage};
+use image_webp::LoopCount;
+
+use crate::playback::{Playback, PlaybackConfig};
+use crate::scale::{complete_size, scale_image, Align, Filter, Scale};
+
+// Frame delays up to this long are replaced, as browsers do, many animations rely on it.
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(10);
+
+// The frame delay to use instead of delays that are too short.
+const CLAMPED_FRAME_DELAY: Duration = Duration::from_millis(100);
 
 /// A manager that manages all images to print.
 pub struct ImageManager {
     /// Image frames and their preferred delay.
-    images: Vec<(DynamicImage, Option<Duration>)>,
-    /// Define whether the first image has been drawn
-    first: bool,
-    index: isize,
+    /// Frames are shared with the painters painting them.
+    images: Vec<(Arc<RgbaImage>, Option<Duration>)>,
+
+    /// The frames in the order to play them, as indices into the images.
+    order: Vec<usize>,
+
+    /// The position of the current frame in the order.
+    position: usize,
+
+    /// The position in the order to continue at when starting the next loop.
+    restart: usize,
+
+    /// The number of loops left to play, including the current one, or forever if not set.
+    loops: Option<u32>,
 }
 
 impl ImageManager {
     /// Intantiate the image manager.
-    pub fn from(images: Vec<(DynamicImage, Option<Duration>)>) -> ImageManager {
+    ///
+    /// The frames are played in the given order, the given number of times, or forever if 0.
+    pub fn from(
+        images: Vec<(RgbaImage, Option<Duration>)>,
+        playback: Playback,
+        loops: u32,
+    ) -> ImageManager {
+        // Ping-pong ends each loop at the first frame, the next loop continues after it
+        let count = images.len();
+        let (order, restart) = match playback {
+            Playback::Forward => ((0..count).collect(), 0),
+            Playback::Reverse => ((0..count).rev().collect(), 0),
+            Playback::PingPong => (
+                (0..count)
+                    .chain((0..count.saturating_sub(1)).rev())
+                    .collect(),
+                1,
+            ),
+        };
+
         ImageManager {
-            images,
-            first: false,
-            index: 0,
+            images: images
+                .into_iter()
+                .map(|(image, frame_delay)| (Arc::new(image), frame_delay))
+                .collect(),
+            order,
+            position: 0,
+            restart,
+            loops: (loops > 0).then_some(loops),
         }
     }
 
     /// Instantiate the image manager, and load the images from the given paths.
-    pub fn load(paths: &[&str], size: (u16, u16)) -> ImageManager {
+    ///
+    /// Images are scaled to the given size, a dimension that isn't set is kept.
+    pub fn load(
+        paths: &[&str],
+        size: (Option<u16>, Option<u16>),
+        scale: Scale,
+        align: Align,
+        filter: Filter,
+        playback: PlaybackConfig,
+    ) -> ImageManager {
         // Show a status message
-        println!("Load and process {} image(s)...", paths.len());
-
-        // Load the images from the paths
-        let image_manager = ImageManager::from(
-            paths
-                .par_iter()
-                .flat_map(|path| load_image(path, size))
-                .collect(),
-        );
+        info!("Load and process {} image(s)...", paths.len());
+
+        // Load the images from the paths, use the first loop count of any animation
+        let loaded: Vec<_> = paths.par_iter().map(|path| load_image(path)).collect();
+        let loops = playback
+            .loops
+            .or_else(|| loaded.iter().find_map(|(_, loops)| *loops))
+            .unwrap_or(0);
+        let frames = playback
+            .frames
+            .select(loaded.into_iter().flat_map(|(frames, _)| frames).collect());
+        if frames.is_empty() {
+            panic!("The selected frame range does not contain any frames");
+        }
 
-        // TODO: process the image slices
+        // Scale the frames to the requested size
+        let frames = frames
+            .into_par_iter()
+            .map(|(image, frame_delay)| {
+                let size = complete_size((image.width(), image.height()), size);
+                (
+                    scale_image(image, size, scale, align, filter).into_rgba8(),
+                    frame_delay,
+                )
+            })
+            .collect();
+        let image_manager = ImageManager::from(frames, playback.playback, loops);
 
         // We succeeded
-        println!("All images have been loaded successfully");
+        info!("All images have been loaded successfully");
 
         image_manager
     }
 
-    /// Tick the image
-    ///
-    /// Returns the desired duration for othis frame.
-    pub fn tick(&mut self, canvas: &mut Canvas) -> Option<Duration> {
-        // Get the image index bound
-        let bound = self.images.len();
-
-        // Just return if the bound is one, as nothing should be updated
-        if self.first && bound == 1 {
-            return None;
-        }
-
-        // Get the image to use
-        let (image, duration) = &mut self.images[self.index as usize % bound];
-
-        // Update the image on the canvas
-        canvas.update_image(image);
+    /// Get the current frame.
+    pub fn frame(&self) -> &Arc<RgbaImage> {
+        &self.images[self.order[self.position]].0
+    }
 
-        // Increase the index
-        self.index += 1;
+    /// Get the preferred delay of the current frame, if it has any.
+    pub fn frame_delay(&self) -> Option<Duration> {
+        self.images[self.order[self.position]].1
+    }
 
-        // We have rendered the first image
-        self.first = true;
+    /// Get the size covering all frames, frames may differ in size.
+    pub fn size(&self) -> (u32, u32) {
+        self.images
+            .iter()
+            .map(|(image, _)| image.dimensions())
+            .fold((0, 0), |(w, h), (iw, ih)| (w.max(iw), h.max(ih)))
+    }
 
-        *duration
+    /// Get the number of frames played in each loop.
+    pub fn frame_count(&self) -> usize {
+        self.order.len()
     }
 
-    /// Start working in the image manager.
+    /// Advance to the next frame, starting the next loop after the last one.
     ///
-    /// This will start walking through all image frames,
-    /// and pushes each frame to all painters,
-    /// with the specified frames per second.
-    pub fn work(&mut self, canvas: &mut Canvas, fps: u32) {
-        loop {
-            // Determine duration to wait, use frame direction and fall back to FPS
-            let frame_delay = self.tick(canvas);
-            let delay = frame_delay
-                .unwrap_or_else(|| Duration::from_millis((1000f32 / (fps as f32)) as u64));
-
-            // Sleep until we need to show the next image
-            sleep(delay);
+    /// Returns `false` once all loops have been played, the last frame is kept.
+    pub fn advance(&mut self) -> bool {
+        if self.position + 1 < self.order.len() {
+            self.position += 1;
+            return true;
+        }
+
+        match &mut self.loops {
+            Some(1) => return false,
+            Some(loops) => *loops -= 1,
+            None => {}
         }
+        self.position = self.restart.min(self.order.len() - 1);
+        true
     }
 }
 
-/// Load the image at the given path, and size it correctly
-fn load_image(path: &str, size: (u16, u16)) -> Vec<(DynamicImage, Option<Duration>)> {
+/// Load the image at the given path.
+///
+/// Returns the frames, and the number of times to play them if the image is an animation
+/// that defines it, 0 to play forever.
+fn load_image(path: &str) -> (Vec<(DynamicImage, Option<Duration>)>, Option<u32>) {
     // Create a path instance
     let path = Path::new(&path);
 
@@ -112,55 +180,76 @@ fn load_image(path: &str, size: (u16, u16)) -> Vec<(DynamicImage, Option<Duratio
         .map(|e| e.to_lowercase());
 
     // Load image(s)
-    let images = match extension.as_deref() {
+    match extension.as_deref() {
         // Load all GIF frames
-        Some("gif") => GifDecoder::new(BufReader::new(File::open(path).unwrap()))
-            .expect("failed to decode GIF file")
-            .into_frames()
-            .collect_frames()
-            .expect("failed to parse GIF frames")
-            .into_iter()
-            .map(|frame| {
-                let frame_delay = Duration::from(frame.delay());
-                (
-                    DynamicImage::ImageRgba8(frame.into_buffer()),
-                    Some(frame_delay),
-                )
-            })
-            .collect(),
+        Some("gif") => {
+            let frames: Vec<_> = GifDecoder::new(BufReader::new(File::open(path).unwrap()))
+                .expect("failed to decode GIF file")
+                .into_frames()
+                .collect_frames()
+                .expect("failed to parse GIF frames")
+                .into_iter()
+                .map(|frame| {
+                    let frame_delay = frame_delay(frame.delay());
+                    (
+                        DynamicImage::ImageRgba8(frame.into_buffer()),
+                        Some(frame_delay),
+                    )
+                })
+                .collect();
+
+            // Browsers play a GIF once, and then repeat it the given number of times
+            let loops = gif::DecodeOptions::new()
+                .read_info(File::open(path).unwrap())
+                .ok()
+                .filter(|_| frames.len() > 1)
+                .map(|decoder| match decoder.repeat() {
+                    gif::Repeat::Finite(repeat) => repeat as u32 + 1,
+                    gif::Repeat::Infinite => 0,
+                });
+            (frames, loops)
+        }
         Some("webp") => {
             let webp = WebPDecoder::new(BufReader::new(File::open(path).unwrap()))
                 .expect("failed to decode WEBP File");
             if !webp.has_animation() {
-                vec![(image::open(path).unwrap(), None)]
-            } else {
-                webp.into_frames()
-                    .collect_frames()
-                    .expect("failed to parse webp frames")
-                    .into_iter()
-                    .map(|frame| {
-                        let frame_delay = Duration::from(frame.delay());
-                        (
-                            DynamicImage::ImageRgba8(frame.into_buffer()),
-                            Some(frame_delay),
-                        )
-                    })
-                    .collect()
+                return (vec![(image::open(path).unwrap(), None)], None);
             }
+            let frames = webp
+                .into_frames()
+                .collect_frames()
+                .expect("failed to parse webp frames")
+                .into_iter()
+                .map(|frame| {
+                    let frame_delay = frame_delay(frame.delay());
+                    (
+                        DynamicImage::ImageRgba8(frame.into_buffer()),
+                        Some(frame_delay),
+                    )
+                })
+                .collect();
+
+            // Read the loop count from the animation header
+            let loops = image_webp::WebPDecoder::new(BufReader::new(File::open(path).unwrap()))
+                .ok()
+                .map(|decoder| match decoder.loop_count() {
+                    LoopCount::Times(times) => times.get() as u32,
+                    LoopCount::Forever => 0,
+                });
+            (frames, loops)
         }
 
         // Load single image
-        _ => vec![(image::open(path).unwrap(), None)],
-    };
-
-    // Resize images to fit the screen
-    images
-        .into_iter()
-        .map(|(image, frame_delay)| {
-            (
-                image.resize_exact(size.0 as u32, size.1 as u32, FilterType::Gaussian),
-                frame_delay,
-            )
-        })
-        .collect()
+        _ => (vec![(image::open(path).unwrap(), None)], None),
+    }
+}
+
+/// Get the delay of an animation frame, replacing delays that are too short.
+fn frame_delay(delay: Delay) -> Duration {
+    let delay = Duration::from(delay);
+    if delay <= MIN_FRAME_DELAY {
+        CLAMPED_FRAME_DELAY
+    } else {
+        delay
+    }
 }
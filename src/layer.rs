@@ -0,0 +1,197 @@
+use std::fs;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+
+use crate::playback::PlaybackConfig;
+use crate::scale::{Align, Filter, Scale};
+
+/// A layer to paint, with its own images, placement and frame rate.
+///
+/// Layers are given as comma separated `key=value` pairs, such as
+/// `image=logo.png,x=10,y=10,w=64,h=64,fps=5,scale=fit,align=center,filter=nearest`.
+/// The `image` key may be repeated for multiple frames. Playback is controlled
+/// with the `playback`, `loops` and `frames` keys.
+#[derive(Clone, Debug)]
+pub struct LayerSpec {
+    /// Image paths, used as frames.
+    pub images: Vec<String>,
+
+    /// Position on the screen.
+    pub offset: (i16, i16),
+
+    /// Size to paint at, the size of the image if not set.
+    /// With only a width or height set, the other follows the aspect ratio.
+    pub size: (Option<u16>, Option<u16>),
+
+    /// Frames per second, for frames without their own delay.
+    pub fps: Option<u32>,
+
+    /// How to scale the images to the size.
+    pub scale: Scale,
+
+    /// Where to place scaled images within the size.
+    pub align: Align,
+
+    /// The filter to resample images with.
+    pub filter: Filter,
+
+    /// How to play the frames.
+    pub playback: PlaybackConfig,
+}
+
+impl FromStr for LayerSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<LayerSpec, String> {
+        let mut layer = LayerSpec {
+            images: Vec::new(),
+            offset: (0, 0),
+            size: (None, None),
+            fps: None,
+            scale: Scale::Stretch,
+            align: Align::Center,
+            filter: Filter::Gaussian,
+            playback: PlaybackConfig::default(),
+        };
+
+        for pair in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", pair))?;
+            let value = value.trim();
+            let invalid = || format!("invalid value for '{}': '{}'", key.trim(), value);
+            match key.trim() {
+                "image" | "i" => layer.images.push(value.to_string()),
+                "x" => layer.offset.0 = value.parse().map_err(|_| invalid())?,
+                "y" => layer.offset.1 = value.parse().map_err(|_| invalid())?,
+                "width" | "w" => layer.size.0 = Some(value.parse().map_err(|_| invalid())?),
+                "height" | "h" => layer.size.1 = Some(value.parse().map_err(|_| invalid())?),
+                "fps" | "r" => layer.fps = Some(value.parse().map_err(|_| invalid())?),
+                "scale" => layer.scale = ValueEnum::from_str(value, true).map_err(|_| invalid())?,
+                "align" => layer.align = ValueEnum::from_str(value, true).map_err(|_| invalid())?,
+                "filter" => {
+                    layer.filter = ValueEnum::from_str(value, true).map_err(|_| invalid())?
+                }
+                "playback" => {
+                    layer.playback.playback =
+                        ValueEnum::from_str(value, true).map_err(|_| invalid())?
+                }
+                "loops" => layer.playback.loops = Some(value.parse().map_err(|_| invalid())?),
+                "frames" => layer.playback.frames = value.parse()?,
+                key => return Err(format!("unknown key '{}'", key)),
+            }
+        }
+
+        if layer.images.is_empty() {
+            return Err("no image given, set one with image=PATH".into());
+        }
+        Ok(layer)
+    }
+}
+
+/// Read layers from the file at the given path.
+///
+/// The file has a layer on each line. Empty lines and lines starting with `#` are skipped.
+/// A file without any layers is rejected.
+pub fn read_layers(path: &str) -> Result<Vec<LayerSpec>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let layers: Vec<LayerSpec> = contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            line.parse()
+                .map_err(|e| format!("{}:{}: {}", path, i + 1, e))
+        })
+        .collect::<Result<_, _>>()?;
+
+    if layers.is_empty() {
+        return Err(format!("{}: no layers found", path));
+    }
+    Ok(layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::playback::Playback;
+
+    #[test]
+    fn parse_layer() {
+        let layer: LayerSpec = "image=a.png, i=b.png,x=-5,y=7,w=64,h=32,fps=5,scale=fit,\
+            align=top-left,filter=nearest,playback=ping-pong,loops=3,frames=1-"
+            .parse()
+            .unwrap();
+        assert_eq!(layer.images, ["a.png", "b.png"]);
+        assert_eq!(layer.offset, (-5, 7));
+        assert_eq!(layer.size, (Some(64), Some(32)));
+        assert_eq!(layer.fps, Some(5));
+        assert_eq!(layer.scale, Scale::Fit);
+        assert_eq!(layer.align, Align::TopLeft);
+        assert_eq!(layer.filter, Filter::Nearest);
+        assert_eq!(layer.playback.playback, Playback::PingPong);
+        assert_eq!(layer.playback.loops, Some(3));
+        assert_eq!(layer.playback.frames.select(vec![0, 1, 2]), [1, 2]);
+    }
+
+    #[test]
+    fn parse_layer_defaults() {
+        let layer: LayerSpec = "image=a.png,".parse().unwrap();
+        assert_eq!(layer.offset, (0, 0));
+        assert_eq!(layer.size, (None, None));
+        assert_eq!(layer.fps, None);
+        assert_eq!(layer.scale, Scale::Stretch);
+        assert_eq!(layer.align, Align::Center);
+        assert_eq!(layer.filter, Filter::Gaussian);
+        assert_eq!(layer.playback.playback, Playback::Forward);
+        assert_eq!(layer.playback.loops, None);
+    }
+
+    #[test]
+    fn parse_invalid_layer() {
+        for spec in [
+            "",
+            "x=5",
+            "image=a.png,x",
+            "image=a.png,x=abc",
+            "image=a.png,x=40000",
+            "image=a.png,w=-1",
+            "image=a.png,scale=huge",
+            "image=a.png,frames=3-1",
+            "image=a.png,color=red",
+        ] {
+            assert!(spec.parse::<LayerSpec>().is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn read_layer_file() {
+        let path = env::temp_dir().join(format!("pixelpwnr-layers-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        fs::write(path, "# Background\nimage=a.png\n\n  image=b.png,x=10  \n").unwrap();
+        let layers = read_layers(path).unwrap();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[1].images, ["b.png"]);
+        assert_eq!(layers[1].offset, (10, 0));
+
+        fs::write(path, "image=a.png\nx=10\n").unwrap();
+        assert!(read_layers(path)
+            .unwrap_err()
+            .ends_with(":2: no image given, set one with image=PATH"));
+
+        fs::write(path, "# Nothing\n\n").unwrap();
+        assert!(read_layers(path).unwrap_err().ends_with("no layers found"));
+
+        fs::remove_file(path).unwrap();
+        assert!(read_layers(path).is_err());
+    }
+}
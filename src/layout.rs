@@ -0,0 +1,194 @@
+use clap::ValueEnum;
+
+use crate::rect::Rect;
+
+/// The layout to divide the canvas over painters with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// Vertical stripes, side by side.
+    Vertical,
+
+    /// Horizontal stripes, stacked.
+    Horizontal,
+
+    /// A grid of tiles.
+    Grid,
+
+    /// Interleaved rows, each painter paints every n-th row.
+    Scanlines,
+
+    /// Small chunks in a shared queue, painters take the next chunk when done.
+    Steal,
+}
+
+impl Layout {
+    /// Divide a canvas of the given size into regions for the given number of painters.
+    ///
+    /// Every pixel is covered exactly once. Remainders are distributed over the
+    /// first painters. Painters that would get no pixels are omitted.
+    pub fn regions(self, size: (u16, u16), count: usize) -> Vec<Vec<Rect>> {
+        let count = count.max(1);
+        let regions: Vec<Vec<Rect>> = match self {
+            Layout::Vertical => split(size.0, count)
+                .into_iter()
+                .map(|(x, w)| vec![Rect::from(x, 0, w, size.1)])
+                .collect(),
+            Layout::Horizontal => split(size.1, count)
+                .into_iter()
+                .map(|(y, h)| vec![Rect::from(0, y, size.0, h)])
+                .collect(),
+            Layout::Grid => {
+                // Pick the number of rows giving tiles closest to square
+                let rows = ((count as f64 * size.1 as f64 / size.0.max(1) as f64)
+                    .sqrt()
+                    .round() as usize)
+                    .clamp(1, count);
+
+                // Divide the painters over the rows, and each row into tiles
+                split(size.1, rows)
+                    .into_iter()
+                    .zip(split(count as u16, rows))
+                    .flat_map(|((y, h), (_, columns))| {
+                        split(size.0, columns as usize)
+                            .into_iter()
+                            .map(move |(x, w)| vec![Rect::from(x, y, w, h)])
+                    })
+                    .collect()
+            }
+            Layout::Steal => vec![vec![Rect::from(0, 0, size.0, size.1)]; count],
+            Layout::Scanlines => (0..count.min(size.1 as usize))
+                .map(|i| {
+                    (i..size.1 as usize)
+                        .step_by(count)
+                        .map(|y| Rect::from(0, y as u16, size.0, 1))
+                        .collect()
+                })
+                .collect(),
+        };
+
+        // Omit empty regions, and painters without any
+        regions
+            .into_iter()
+            .map(|regions| {
+                regions
+                    .into_iter()
+                    .filter(|region| region.w > 0 && region.h > 0)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|regions| !regions.is_empty())
+            .collect()
+    }
+}
+
+/// Split a length into the given number of parts, as `(start, length)` pairs.
+///
+/// The remainder is distributed over the first parts, one each.
+fn split(len: u16, parts: usize) -> Vec<(u16, u16)> {
+    let base = len as usize / parts;
+    let remainder = len as usize % parts;
+    let mut start = 0;
+    (0..parts)
+        .map(|i| {
+            let part = base + (i < remainder) as usize;
+            let range = (start as u16, part as u16);
+            start += part;
+            range
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZES: [(u16, u16); 6] = [(1, 1), (7, 3), (3, 7), (100, 37), (5, 200), (641, 359)];
+    const COUNTS: [usize; 7] = [0, 1, 2, 3, 7, 16, 33];
+
+    /// Count how many times each pixel of a canvas of the given size is covered.
+    fn coverage(regions: &[Vec<Rect>], size: (u16, u16)) -> Vec<u32> {
+        let mut covered = vec![0; size.0 as usize * size.1 as usize];
+        for region in regions.iter().flatten() {
+            assert!(region.w > 0 && region.h > 0, "empty region");
+            assert!(region.x + region.w <= size.0 && region.y + region.h <= size.1);
+            for y in region.y..region.y + region.h {
+                for x in region.x..region.x + region.w {
+                    covered[y as usize * size.0 as usize + x as usize] += 1;
+                }
+            }
+        }
+        covered
+    }
+
+    #[test]
+    fn regions_cover_canvas_once() {
+        let layouts = [
+            Layout::Vertical,
+            Layout::Horizontal,
+            Layout::Grid,
+            Layout::Scanlines,
+        ];
+        for layout in layouts {
+            for size in SIZES {
+                for count in COUNTS {
+                    let regions = layout.regions(size, count);
+                    assert!(!regions.is_empty() && regions.len() <= count.max(1));
+                    assert!(
+                        coverage(&regions, size).iter().all(|&c| c == 1),
+                        "{:?} of {:?} over {} painters",
+                        layout,
+                        size,
+                        count
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn regions_are_balanced() {
+        // Remainders go to the first painters, one pixel row or column each
+        let widths: Vec<u16> = Layout::Vertical
+            .regions((10, 4), 4)
+            .iter()
+            .map(|regions| regions[0].w)
+            .collect();
+        assert_eq!(widths, [3, 3, 2, 2]);
+
+        let rows: Vec<usize> = Layout::Scanlines
+            .regions((4, 10), 4)
+            .iter()
+            .map(Vec::len)
+            .collect();
+        assert_eq!(rows, [3, 3, 2, 2]);
+    }
+
+    #[test]
+    fn regions_omit_idle_painters() {
+        assert_eq!(Layout::Vertical.regions((3, 5), 8).len(), 3);
+        assert_eq!(Layout::Horizontal.regions((5, 3), 8).len(), 3);
+        assert_eq!(Layout::Scanlines.regions((5, 3), 8).len(), 3);
+        assert_eq!(Layout::Grid.regions((1, 1), 8).len(), 1);
+    }
+
+    #[test]
+    fn steal_shares_canvas() {
+        for size in SIZES {
+            for count in COUNTS {
+                let regions = Layout::Steal.regions(size, count);
+                assert_eq!(regions.len(), count.max(1));
+                for painter in &regions {
+                    assert!(coverage(std::slice::from_ref(painter), size)
+                        .iter()
+                        .all(|&c| c == 1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_distributes_remainder() {
+        assert_eq!(split(10, 3), [(0, 4), (4, 3), (7, 3)]);
+        assert_eq!(split(2, 4), [(0, 1), (1, 1), (2, 0), (2, 0)]);
+        assert_eq!(split(0, 2), [(0, 0), (0, 0)]);
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// The minimum interval between repeated messages with the same key.
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A logger writing messages to stderr.
+///
+/// Messages are prefixed with their level, and the name of the thread they
+/// were logged from, such as `painter-3`.
+struct Logger {
+    level: LevelFilter,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let thread = thread::current();
+        let mut stderr = std::io::stderr().lock();
+        let _ = match thread.name().filter(|name| *name != "main") {
+            Some(name) => writeln!(stderr, "[{} {}] {}", record.level(), name, record.args()),
+            None => writeln!(stderr, "[{}] {}", record.level(), record.args()),
+        };
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Initialize logging, with the given verbosity.
+///
+/// A verbosity of `0` logs informational messages and up,
+/// every step up or down shows more or fewer messages.
+pub fn init(verbosity: i8) {
+    let level = match verbosity {
+        i8::MIN..=-3 => LevelFilter::Off,
+        -2 => LevelFilter::Error,
+        -1 => LevelFilter::Warn,
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        2..=i8::MAX => LevelFilter::Trace,
+    };
+
+    log::set_logger(Box::leak(Box::new(Logger { level }))).expect("Failed to initialize logger");
+    log::set_max_level(level);
+}
+
+/// The state of rate limited messages, by key.
+///
+/// Holds when a message was last logged, and how many were suppressed since.
+static RATE_LIMITS: OnceLock<Mutex<HashMap<&'static str, (Instant, u64)>>> = OnceLock::new();
+
+/// Log a message, unless a message with the same key was logged recently.
+///
+/// This prevents many threads running into the same problem, such as a dropped
+/// server, from flooding the log. The number of suppressed messages is reported
+/// with the next message that is logged.
+pub fn log_limited(level: Level, key: &'static str, args: fmt::Arguments) {
+    if !log::log_enabled!(level) {
+        return;
+    }
+
+    // Determine whether to log, or to suppress the message
+    let suppressed = {
+        let mut limits = RATE_LIMITS
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap();
+        let now = Instant::now();
+        match limits.get_mut(key) {
+            Some((last, suppressed)) if now - *last < RATE_LIMIT_INTERVAL => {
+                *suppressed += 1;
+                return;
+            }
+            Some((last, suppressed)) => {
+                *last = now;
+                std::mem::take(suppressed)
+            }
+            None => {
+                limits.insert(key, (now, 0));
+                0
+            }
+        }
+    };
+
+    if suppressed > 0 {
+        log::log!(
+            level,
+            "{} ({} similar message(s) suppressed)",
+            args,
+            suppressed
+        );
+    } else {
+        log::log!(level, "{}", args);
+    }
+}
+
+/// Log a warning, rate limited by the given key.
+///
+/// See [`log_limited`].
+macro_rules! warn_limited {
+    ($key:expr, $($arg:tt)+) => {
+        $crate::logger::log_limited(log::Level::Warn, $key, format_args!($($arg)+))
+    };
+}
+
+pub(crate) use warn_limited;
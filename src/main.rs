@@ -1,67 +1,218 @@
 mod args;
 mod color;
+mod compositor;
 mod image_manager;
+mod layer;
+mod layout;
+mod logger;
 mod painter;
 mod pix;
+mod playback;
 mod rect;
+mod scale;
+mod stats;
 
 use std::io::Error;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use args::ArgHandler;
-use image_manager::ImageManager;
+use log::{error, info, warn};
+
+use args::{ArgHandler, Command, GrabArguments, ServeArguments};
+use compositor::Compositor;
+use layer::LayerSpec;
 use pix::canvas::Canvas;
-use pix::client::Client;
+use pix::client::{Client, ClientConfig};
+use pix::grab::grab;
+use pix::server::Server;
+use rect::Rect;
+use stats::{Snapshot, Stats};
 
 /// Main application entrypoint.
 fn main() {
     // Parse CLI arguments
     let arg_handler = ArgHandler::parse();
+    logger::init(arg_handler.verbosity());
 
-    // Start
-    start(&arg_handler);
+    // Run the subcommand, or start
+    match arg_handler.command() {
+        Some(Command::Serve(args)) => serve(args),
+        Some(Command::Grab(args)) => grab_snapshot(args),
+        None => start(&arg_handler),
+    }
 }
 
 /// Start pixelflutting.
 fn start(arg_handler: &ArgHandler) {
     // Start
-    println!("Starting... (use CTRL+C to stop)");
-
-    // Determine the size to use
-    let size = if let (Some(w), Some(h)) = arg_handler.size() {
-        (w, h)
-    } else {
-        let server_canvas =
-            gather_host_facts(arg_handler).expect("Failed to gather facts about pixelflut server");
-        (
-            arg_handler.size().0.unwrap_or(server_canvas.0),
-            arg_handler.size().1.unwrap_or(server_canvas.1),
-        )
-    };
+    info!("Starting... (use CTRL+C to stop)");
+    let started = Instant::now();
+    let stop = handle_stop();
+
+    // Gather the screen size, to determine the image size to use and to clip to the screen
+    let screen = gather_host_facts(arg_handler);
+
+    // Determine the layers to paint, the image is the bottom layer
+    let mut layers = Vec::new();
+    let images = arg_handler.image_paths();
+    if !images.is_empty() {
+        let size = match (arg_handler.size(), &screen) {
+            ((Some(w), Some(h)), _) => (w, h),
+            ((w, h), Ok(screen)) => (w.unwrap_or(screen.0), h.unwrap_or(screen.1)),
+            (_, Err(e)) => panic!("Failed to gather facts about pixelflut server: {}", e),
+        };
+        layers.push(LayerSpec {
+            images: images.iter().map(|path| path.to_string()).collect(),
+            offset: arg_handler.offset(),
+            size: (Some(size.0), Some(size.1)),
+            fps: None,
+            scale: arg_handler.scale(),
+            align: arg_handler.align(),
+            filter: arg_handler.filter(),
+            playback: arg_handler.playback_config(),
+        });
+    }
+    layers.extend(
+        arg_handler
+            .layers()
+            .unwrap_or_else(|e| panic!("Failed to read layers: {}", e)),
+    );
+    let screen = screen
+        .map_err(|e| warn!("Failed to gather screen size, not clipping to it: {}", e))
+        .ok();
 
-    // Create a new pixelflut canvas
+    // Load the images of all layers
+    let mut compositor = Compositor::load(
+        &layers,
+        arg_handler.fps(),
+        arg_handler.speed(),
+        arg_handler.on_end(),
+    );
+
+    // Create a new pixelflut canvas, covering all layers
     let mut canvas = Canvas::new(
         arg_handler.host(),
         arg_handler.count(),
-        size,
-        arg_handler.offset(),
-        arg_handler.binary(),
-        arg_handler.flush(),
+        compositor.size(),
+        compositor.offset(),
+        screen,
+        arg_handler.client_config(),
+        arg_handler.painter_config(),
+    );
+
+    // Report statistics periodically
+    let reporter = arg_handler.stats_interval().map(|interval| {
+        let stats = canvas.stats();
+        let json = arg_handler.stats_json();
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || stats::report(&stats, interval, json, &rx));
+        (thread, tx)
+    });
+
+    // Start the work in the compositor, to walk through the frames
+    compositor.work(&mut canvas, &stop);
+
+    // Stop reporting and all painters, and show what we've done
+    if let Some((thread, tx)) = reporter {
+        drop(tx);
+        let _ = thread.join();
+    }
+    info!("Stopping painters...");
+    let stats = canvas.stop();
+    print_summary(&stats, started.elapsed());
+}
+
+/// Handle CTRL+C, to stop gracefully.
+///
+/// A stop signal is sent on the returned channel on the first CTRL+C,
+/// the process is forcefully quit on the second.
+fn handle_stop() -> Receiver<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut stopping = false;
+    ctrlc::set_handler(move || {
+        if stopping {
+            error!("Forcefully quitting");
+            process::exit(130);
+        }
+        stopping = true;
+        info!("Stopping... (use CTRL+C again to force quit)");
+        let _ = tx.send(());
+    })
+    .expect("Failed to set CTRL+C handler");
+    rx
+}
+
+/// Print a summary of the statistics of all painters.
+fn print_summary(stats: &[Arc<Stats>], runtime: Duration) {
+    let snapshots: Vec<Snapshot> = stats.iter().map(|stats| stats.snapshot()).collect();
+    let total = Snapshot::total(&snapshots);
+    info!(
+        "Sent {} pixels ({:.1} MB) in {:.1}s with {} painter(s), {} flush(es), {} error(s), {} reconnect(s)",
+        total.pixels,
+        total.bytes as f64 / 1_000_000.0,
+        runtime.as_secs_f64(),
+        stats.len(),
+        total.flushes,
+        total.errors,
+        total.reconnects,
     );
+}
 
-    // Load the image manager
-    let mut image_manager = ImageManager::load(&arg_handler.image_paths(), size);
+/// Serve a local pixelflut canvas, to benchmark throughput.
+fn serve(args: &ServeArguments) {
+    Server::new(args.size())
+        .serve(args.host())
+        .expect("Failed to run pixelflut server");
+}
+
+/// Grab a snapshot of the canvas, and save it as PNG.
+fn grab_snapshot(args: &GrabArguments) {
+    let config = ClientConfig::default();
 
-    // Start the work in the image manager, to walk through the frames
-    image_manager.work(&mut canvas, arg_handler.fps());
+    // Determine the area to grab, default to the rest of the screen
+    let (x, y) = args.offset();
+    let (w, h) = match args.size() {
+        (Some(w), Some(h)) => (w, h),
+        size => {
+            let screen = Client::connect(args.host().to_string(), &config)
+                .and_then(|mut client| client.read_screen_size())
+                .unwrap_or_else(|e| {
+                    panic!("Failed to gather screen size from pixelflut server: {}", e)
+                });
+            (
+                size.0.unwrap_or(screen.0.saturating_sub(x)),
+                size.1.unwrap_or(screen.1.saturating_sub(y)),
+            )
+        }
+    };
+
+    info!(
+        "Grabbing {}x{} pixels at ({}, {}) with {} connection(s)...",
+        w,
+        h,
+        x,
+        y,
+        args.count(),
+    );
+    let image = grab(args.host(), &config, Rect::from(x, y, w, h), args.count())
+        .expect("Failed to grab pixels from pixelflut server");
+    image
+        .save_with_format(args.output(), image::ImageFormat::Png)
+        .expect("Failed to save snapshot");
+    info!("Saved snapshot to {}", args.output());
 }
 
 /// Gather important facts about the host.
 fn gather_host_facts(arg_handler: &ArgHandler) -> Result<(u16, u16), Error> {
     // Set up a client, and get the screen size
-    let size = Client::connect(arg_handler.host().to_string(), false, false)?.read_screen_size()?;
+    let size = Client::connect(arg_handler.host().to_string(), &arg_handler.client_config())?
+        .read_screen_size()?;
 
     // Print status
-    println!("Gathered screen size: {}x{}", size.0, size.1);
+    info!("Gathered screen size: {}x{}", size.0, size.1);
 
     Ok(size)
 }
@@ -0,0 +1,28 @@
+use std::collections::BinaryHeap;
+use std::sync::Mutex;
+
+/// A queue of damaged pixels that should be repainted.
+///
+/// Pixels are in canvas coordinates, and prioritized by the number of times they have been damaged,
+/// so the most contested pixels are repainted first.
+#[derive(Default)]
+pub struct Damage {
+    /// Damaged pixels, with their damage count.
+    queue: Mutex<BinaryHeap<(u32, u16, u16)>>,
+}
+
+impl Damage {
+    /// Report a damaged pixel, with the number of times it has been damaged.
+    pub fn push(&self, count: u32, x: u16, y: u16) {
+        self.queue.lock().unwrap().push((count, x, y));
+    }
+
+    /// Take the most contested damaged pixels, at most `max`.
+    pub fn take(&self, max: usize) -> Vec<(u16, u16)> {
+        let mut queue = self.queue.lock().unwrap();
+        (0..max.min(queue.len()))
+            .filter_map(|_| queue.pop())
+            .map(|(_, x, y)| (x, y))
+            .collect()
+    }
+}
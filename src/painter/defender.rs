@@ -0,0 +1,195 @@
+use std::io::Error;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::RgbaImage;
+
+use crate::painter::damage::Damage;
+use crate::pix::client::Client;
+use crate::rect::Rect;
+
+// The number of pixels to sample on each unit of work.
+const SAMPLE_COUNT: usize = 256;
+
+// The time to wait for a new image, after sampling no pixel to defend.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A defender that reads back pixels from a pixelflut panel.
+///
+/// Sampled pixels are compared to the image that is painted, overwritten
+/// pixels are reported to the damage queue of the painter owning them.
+pub struct Defender {
+    client: Option<Client>,
+    offset: (i16, i16),
+    image: Option<Arc<RgbaImage>>,
+
+    /// The painter regions, and their damage queues.
+    targets: Vec<(Rect, Arc<Damage>)>,
+
+    /// The number of times each pixel has been damaged in the current image.
+    counts: Vec<u32>,
+
+    /// The position in the sampling order.
+    position: usize,
+
+    /// The step through all pixels in the sampling order.
+    /// Coprime with the number of pixels, so all pixels are visited.
+    stride: usize,
+
+    /// Whether the image channel was closed, and defending should stop.
+    stopped: bool,
+}
+
+impl Defender {
+    /// Create a new defender.
+    pub fn new(offset: (i16, i16), targets: Vec<(Rect, Arc<Damage>)>) -> Defender {
+        Defender {
+            client: None,
+            offset,
+            image: None,
+            targets,
+            counts: Vec::new(),
+            position: 0,
+            stride: 1,
+            stopped: false,
+        }
+    }
+
+    /// Perform work.
+    /// Sample some pixels, and report the damaged ones.
+    pub fn work(&mut self, img_receiver: &Receiver<Arc<RgbaImage>>) -> Result<(), Error> {
+        // Wait for an image, if no image has been set yet
+        if self.image.is_none() {
+            match img_receiver.recv() {
+                Ok(image) => self.set_image(image),
+                Err(_) => {
+                    self.stopped = true;
+                    return Ok(());
+                }
+            }
+        }
+
+        // Update the image to defend, stop when asked to
+        self.receive(img_receiver);
+        if self.stopped {
+            return Ok(());
+        }
+
+        let (Some(client), Some(image)) = (&mut self.client, &self.image) else {
+            return Ok(());
+        };
+        let w = image.width();
+        let total = self.counts.len();
+        let mut sampled = false;
+
+        for _ in 0..SAMPLE_COUNT.min(total) {
+            // Walk through all pixels in a scattered order
+            self.position = (self.position + self.stride) % total;
+            let (x, y) = (
+                (self.position as u32 % w) as u16,
+                (self.position as u32 / w) as u16,
+            );
+
+            // Only defend opaque pixels, translucent pixels blend with the panel
+            let expected = image.get_pixel(x as u32, y as u32);
+            if expected[3] != 255 {
+                continue;
+            }
+
+            // Only defend pixels painted by a painter, others are off-screen
+            let Some((_, damage)) = self
+                .targets
+                .iter()
+                .find(|(region, _)| region.contains(x, y))
+            else {
+                continue;
+            };
+            let screen_x = (x as i32 + self.offset.0 as i32) as u16;
+            let screen_y = (y as i32 + self.offset.1 as i32) as u16;
+
+            // Report the pixel if it was overwritten
+            let color = client.read_pixel(screen_x, screen_y)?;
+            sampled = true;
+            if [color.r, color.g, color.b] == expected.0[..3] {
+                continue;
+            }
+            self.counts[self.position] += 1;
+            damage.push(self.counts[self.position], x, y);
+        }
+
+        // Don't spin if there is little to defend, wait a while for a new image instead
+        if !sampled {
+            match img_receiver.recv_timeout(IDLE_TIMEOUT) {
+                Ok(image) => self.set_image(image),
+                Err(RecvTimeoutError::Disconnected) => self.stopped = true,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receive an image update, if there is any.
+    ///
+    /// Images queued up are skipped, only the latest one is set.
+    /// The defender is stopped if the image channel is closed.
+    pub fn receive(&mut self, img_receiver: &Receiver<Arc<RgbaImage>>) {
+        let mut image = None;
+        loop {
+            match img_receiver.try_recv() {
+                Ok(next) => image = Some(next),
+                Err(TryRecvError::Disconnected) => {
+                    self.stopped = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        if let Some(image) = image {
+            self.set_image(image);
+        }
+    }
+
+    /// Update the image that should be defended.
+    pub fn set_image(&mut self, image: Arc<RgbaImage>) {
+        let total = image.width() as usize * image.height() as usize;
+        self.counts = vec![0; total];
+        self.position = 0;
+        self.stride = scatter_stride(total);
+        self.image = Some(image);
+    }
+
+    /// Whether the defender has been stopped, because its image channel was closed.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Update the client.
+    pub fn set_client(&mut self, client: Option<Client>) {
+        self.client = client;
+    }
+}
+
+/// Find a stride to walk through `total` items in a scattered order.
+///
+/// The stride is coprime with `total`, so stepping through the items
+/// visits each of them exactly once before repeating.
+fn scatter_stride(total: usize) -> usize {
+    // Start around the golden ratio, which scatters nicely
+    let mut stride = (total as f64 * 0.618) as usize;
+    while stride > 1 && gcd(stride, total) != 1 {
+        stride -= 1;
+    }
+    stride.max(1)
+}
+
+/// Get the greatest common divisor of two numbers.
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
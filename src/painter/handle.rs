@@ -1,45 +1,80 @@
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::thread::JoinHandle;
 
-use image::DynamicImage;
+use image::RgbaImage;
 
+use crate::painter::damage::Damage;
+use crate::painter::view::View;
 use crate::rect::Rect;
+use crate::stats::Stats;
 
 /// A handle to a painter thread.
 ///
 /// This also holds a channel to the painter thread,
 /// to allow image updates to be pushed to the thread.
 pub struct Handle {
-    #[allow(dead_code)]
-    thread: JoinHandle<u32>,
+    thread: JoinHandle<()>,
     area: Rect,
-    image_sender: Sender<DynamicImage>,
+    regions: Vec<Rect>,
+    image_sender: Sender<View>,
+    damage: Option<Arc<Damage>>,
+    stats: Arc<Stats>,
 }
 
 impl Handle {
     /// Create a new handle from the given properties.
-    pub fn new(thread: JoinHandle<u32>, area: Rect, image_sender: Sender<DynamicImage>) -> Handle {
+    pub fn new(
+        thread: JoinHandle<()>,
+        area: Rect,
+        regions: Vec<Rect>,
+        image_sender: Sender<View>,
+        damage: Option<Arc<Damage>>,
+        stats: Arc<Stats>,
+    ) -> Handle {
         Handle {
             thread,
             area,
+            regions,
             image_sender,
+            damage,
+            stats,
         }
     }
 
+    /// Get the regions the painter paints.
+    pub fn regions(&self) -> &[Rect] {
+        &self.regions
+    }
+
+    /// Get the damage queue of the painter, if defending.
+    pub fn damage(&self) -> Option<Arc<Damage>> {
+        self.damage.clone()
+    }
+
+    /// Get the statistics of the painter.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// Stop the painter, without waiting for it to finish.
+    ///
+    /// This closes the image channel, which makes the painter quit
+    /// its connection after the current batch.
+    /// Returns the painter thread, to wait for.
+    pub fn stop(self) -> JoinHandle<()> {
+        drop(self.image_sender);
+        self.thread
+    }
+
     /// Push an image update.
-    pub fn update_image(&self, full_image: &mut DynamicImage) {
-        // Crop the image to the area
-        let image = full_image.crop(
-            self.area.x as u32,
-            self.area.y as u32,
-            self.area.w as u32,
-            self.area.h as u32,
-        );
-
-        // Push a new image to the thread
+    ///
+    /// The painter gets a view into its area of the shared frame.
+    pub fn update_image(&self, frame: &Arc<RgbaImage>) {
+        // Push a new view to the thread
         // TODO: return this result
         self.image_sender
-            .send(image)
+            .send(View::new(frame.clone(), self.area))
             .expect("Failed to send image update to painter");
     }
 }
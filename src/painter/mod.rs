@@ -1,3 +1,9 @@
 // Reexport modules
+pub mod damage;
+pub mod defender;
 pub mod handle;
+pub mod order;
+#[allow(clippy::module_inception)]
 pub mod painter;
+pub mod queue;
+pub mod view;
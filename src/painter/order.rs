@@ -0,0 +1,245 @@
+use clap::ValueEnum;
+
+// The stride between pixels in the first interleaved sub-pass.
+const INTERLEAVE_STRIDE: u16 = 4;
+
+/// The order in which a painter paints the pixels of its area.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Order {
+    /// Row by row, left to right.
+    Rows,
+
+    /// Column by column, top to bottom.
+    Columns,
+
+    /// Randomly shuffled, from the seed.
+    Random,
+
+    /// A coarse grid first, refined with each sub-pass.
+    Interleaved,
+
+    /// Along a Hilbert curve, keeping nearby pixels together.
+    Hilbert,
+
+    /// Spiraling outwards from the centre.
+    Spiral,
+
+    /// Concentric rings, from the edges inwards.
+    Edges,
+}
+
+impl Order {
+    /// Get the pixels of an area of the given size, in this order.
+    ///
+    /// The seed is used for random orders.
+    pub fn pixels(self, w: u16, h: u16, seed: u64) -> Vec<(u16, u16)> {
+        match self {
+            Order::Rows => (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).collect(),
+            Order::Columns => (0..w).flat_map(|x| (0..h).map(move |y| (x, y))).collect(),
+            Order::Random => {
+                let mut pixels = Order::Rows.pixels(w, h, seed);
+                shuffle(&mut pixels, seed);
+                pixels
+            }
+            Order::Interleaved => interleaved(w, h),
+            Order::Hilbert => hilbert(w, h),
+            Order::Spiral => spiral(w, h),
+            Order::Edges => {
+                let mut pixels = Order::Rows.pixels(w, h, seed);
+                pixels.sort_by_key(|&(x, y)| x.min(y).min(w - 1 - x).min(h - 1 - y));
+                pixels
+            }
+        }
+    }
+}
+
+/// Shuffle the given items with a Fisher-Yates shuffle, seeded by `seed`.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = seed;
+    for i in (1..items.len()).rev() {
+        let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// Generate the next pseudo random number with SplitMix64.
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Get the pixels in interleaved order.
+///
+/// Starting with a coarse grid, the stride is halved on each sub-pass,
+/// only visiting pixels not visited before.
+fn interleaved(w: u16, h: u16) -> Vec<(u16, u16)> {
+    let mut pixels = Vec::with_capacity(w as usize * h as usize);
+    let mut stride = INTERLEAVE_STRIDE;
+    let mut previous: Option<u16> = None;
+    loop {
+        for y in (0..h).step_by(stride as usize) {
+            for x in (0..w).step_by(stride as usize) {
+                if previous.is_none_or(|p| x % p != 0 || y % p != 0) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        if stride == 1 {
+            return pixels;
+        }
+        previous = Some(stride);
+        stride /= 2;
+    }
+}
+
+/// Get the pixels along a Hilbert curve.
+///
+/// The curve covers the smallest enclosing power of two square,
+/// parts of it outside the area are skipped.
+/// Distances are 64-bit, as the square of the largest sides doesn't fit 32 bits.
+fn hilbert(w: u16, h: u16) -> Vec<(u16, u16)> {
+    let n = (w.max(h) as u64).next_power_of_two();
+    let mut pixels = Vec::with_capacity(w as usize * h as usize);
+    hilbert_walk(n, 0, n, (w as u64, h as u64), &mut pixels);
+    pixels
+}
+
+/// Walk the part of a Hilbert curve of size `n` from distance `d` covering an `s` sized square.
+///
+/// Quadrants outside the area of the given size are skipped as a whole,
+/// so long and narrow areas don't walk the whole enclosing square.
+fn hilbert_walk(n: u64, d: u64, s: u64, size: (u64, u64), pixels: &mut Vec<(u16, u16)>) {
+    // The curve covers an aligned square, its corner is found from any point on it
+    let (x, y) = hilbert_point(n, d);
+    let (x, y) = (x & !(s - 1), y & !(s - 1));
+    if x >= size.0 || y >= size.1 {
+        return;
+    }
+
+    if s == 1 {
+        pixels.push((x as u16, y as u16));
+        return;
+    }
+    for quadrant in 0..4 {
+        hilbert_walk(n, d + quadrant * s * s / 4, s / 2, size, pixels);
+    }
+}
+
+/// Convert a distance along a Hilbert curve of size `n` into a point.
+fn hilbert_point(n: u64, d: u64) -> (u64, u64) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+
+        // Rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+/// Get the pixels spiraling outwards from the centre.
+fn spiral(w: u16, h: u16) -> Vec<(u16, u16)> {
+    let total = w as usize * h as usize;
+    let mut pixels = Vec::with_capacity(total);
+    let (mut x, mut y) = ((w / 2) as i32, (h / 2) as i32);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+    // Walk legs of increasing length, turning after each leg
+    let mut leg = 1;
+    let mut direction = 0;
+    while pixels.len() < total {
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction % 4];
+            for _ in 0..leg {
+                if (0..w as i32).contains(&x) && (0..h as i32).contains(&y) {
+                    pixels.push((x as u16, y as u16));
+                }
+                x += dx;
+                y += dy;
+            }
+            direction += 1;
+        }
+        leg += 1;
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    /// Assert the pixels cover an area of the given size, each pixel exactly once.
+    fn assert_permutation(pixels: &[(u16, u16)], w: u16, h: u16) {
+        let mut seen = vec![false; w as usize * h as usize];
+        for &(x, y) in pixels {
+            assert!(x < w && y < h, "({}, {}) outside {}x{}", x, y, w, h);
+            let index = y as usize * w as usize + x as usize;
+            assert!(!seen[index], "({}, {}) visited twice", x, y);
+            seen[index] = true;
+        }
+        assert_eq!(pixels.len(), seen.len());
+    }
+
+    #[test]
+    fn pixels_are_permutation() {
+        for &order in Order::value_variants() {
+            for (w, h) in [(1, 1), (1, 9), (9, 1), (7, 3), (16, 16), (33, 5), (64, 48)] {
+                assert_permutation(&order.pixels(w, h, 42), w, h);
+            }
+        }
+    }
+
+    #[test]
+    fn pixels_of_empty_area() {
+        for &order in Order::value_variants() {
+            assert!(order.pixels(0, 0, 42).is_empty());
+            assert!(order.pixels(0, 5, 42).is_empty());
+            assert!(order.pixels(5, 0, 42).is_empty());
+        }
+    }
+
+    #[test]
+    fn hilbert_follows_curve() {
+        // Skipping quadrants must keep the order of walking the whole curve
+        for (w, h) in [(5, 3), (16, 16), (17, 9), (1, 40)] {
+            let n = (w.max(h) as u64).next_power_of_two();
+            let curve: Vec<(u16, u16)> = (0..n * n)
+                .map(|d| hilbert_point(n, d))
+                .filter(|&(x, y)| x < w as u64 && y < h as u64)
+                .map(|(x, y)| (x as u16, y as u16))
+                .collect();
+            assert_eq!(hilbert(w, h), curve);
+        }
+    }
+
+    #[test]
+    fn hilbert_large_side() {
+        assert_permutation(&hilbert(40000, 2), 40000, 2);
+        assert_permutation(&hilbert(3, u16::MAX), 3, u16::MAX);
+    }
+
+    #[test]
+    fn random_is_seeded() {
+        assert_eq!(Order::Random.pixels(8, 8, 1), Order::Random.pixels(8, 8, 1));
+        assert_ne!(Order::Random.pixels(8, 8, 1), Order::Random.pixels(8, 8, 2));
+    }
+}
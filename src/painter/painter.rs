@@ -1,104 +1,530 @@
 use std::io::Error;
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
 
-use image::{DynamicImage, Pixel};
+use image::Pixel;
+use log::debug;
 
 use crate::color::Color;
+use crate::layout::Layout;
+use crate::painter::damage::Damage;
+use crate::painter::order::Order;
+use crate::painter::queue::WorkQueue;
+use crate::painter::view::View;
 use crate::pix::client::Client;
 use crate::rect::Rect;
+use crate::stats::Stats;
+
+// The maximum number of damaged pixels to repaint before each batch.
+const REPAIR_COUNT: usize = 1024;
+
+/// Configuration for painters.
+#[derive(Copy, Clone)]
+pub struct PainterConfig {
+    /// Paint all pixels every this many passes, only changed pixels in between.
+    pub refresh: usize,
+
+    /// The order to paint the pixels of an area in.
+    pub order: Order,
+
+    /// The seed for random orders.
+    pub seed: u64,
+
+    /// The layout to divide the canvas over painters with.
+    pub layout: Layout,
+
+    /// The size of the chunks to take from the shared work queue, when stealing work.
+    pub chunk_size: u16,
+
+    /// Whether to read back the canvas, and repaint overwritten pixels first.
+    pub defend: bool,
+}
+
+impl PainterConfig {
+    /// Get the seed for random orders in the given area.
+    ///
+    /// Each area is seeded differently, so painters don't move in lockstep.
+    pub fn seed(&self, area: Rect) -> u64 {
+        self.seed ^ ((area.x as u64) << 16 | area.y as u64)
+    }
+}
 
 /// A painter that paints on a pixelflut panel.
 pub struct Painter {
     client: Option<Client>,
     area: Rect,
     offset: (i16, i16),
-    image: Option<DynamicImage>,
+    image: Option<View>,
+
+    /// The number of images received, identifying the last one.
+    /// Painters sharing a work queue receive the same images, so this matches between them.
+    generation: usize,
+
+    /// The latest image generation counted as dropped by this painter.
+    dropped: usize,
+
+    /// The queue of chunks to paint.
+    /// Holds a single chunk of the own regions, unless it is shared to steal work.
+    queue: Arc<WorkQueue>,
+
+    /// Paint all pixels every this many passes, only changed pixels in between.
+    refresh: usize,
+
+    /// Damaged pixels to repaint first, when defending.
+    damage: Option<Arc<Damage>>,
+
+    /// Statistics of this painter.
+    stats: Arc<Stats>,
+
+    /// Whether the image channel was closed, and painting should stop.
+    stopped: bool,
+}
+
+/// A batch of pre-rendered pixel commands.
+struct Batch {
+    /// The encoded commands.
+    data: Vec<u8>,
+
+    /// The number of pixels in this batch.
+    pixels: u64,
+
+    /// The number of the given pixels handled up to and including this batch.
+    end: usize,
+}
+
+/// A chunk of pixels, painted as a unit.
+pub struct Chunk {
+    /// All pixels of the chunk, relative to the area, in the order to paint them.
+    pixels: Vec<(u16, u16)>,
+
+    /// The bounds of all pixels, relative to the area.
+    bounds: Rect,
+
+    /// The image being painted.
+    image: Option<View>,
+
+    /// The generation of the image being painted.
+    generation: usize,
+
+    /// Pixels to paint for the last image update, relative to the area.
+    /// `None` if all pixels must be painted.
+    changed: Option<Vec<(u16, u16)>>,
+
+    /// Whether the last image update has been painted completely.
+    done: bool,
+
+    /// The number of pixels painted of the pass for the last image update, while not done.
+    /// Counts the changed pixels if there are any, all pixels otherwise.
+    painted: usize,
+
+    /// Pre-rendered commands to paint all pixels, in batches.
+    /// Rendered when first painting a new image.
+    commands: Option<Vec<Batch>>,
+
+    /// Pre-rendered commands to paint the changed pixels, in batches.
+    changed_commands: Option<Vec<Batch>>,
+
+    /// Number of passes painted since the last image update.
+    passes: usize,
+}
+
+impl Chunk {
+    /// Create a chunk painting the given pixels.
+    pub fn new(pixels: Vec<(u16, u16)>) -> Chunk {
+        let pixel_rects: Vec<Rect> = pixels
+            .iter()
+            .map(|&(x, y)| Rect::from(x, y, 1, 1))
+            .collect();
+        Chunk {
+            bounds: Rect::bounds(&pixel_rects),
+            pixels,
+            image: None,
+            generation: 0,
+            changed: None,
+            done: false,
+            painted: 0,
+            commands: None,
+            changed_commands: None,
+            passes: 0,
+        }
+    }
+
+    /// Get the pixels not painted yet for the last image update, in paint order.
+    fn unpainted(&self) -> &[(u16, u16)] {
+        if self.done {
+            return &[];
+        }
+        let pixels = self.changed.as_deref().unwrap_or(&self.pixels);
+        &pixels[self.painted.min(pixels.len())..]
+    }
+
+    /// Switch to the given image, with the given generation.
+    ///
+    /// The pixels that differ from the previous image are determined,
+    /// so they can be painted first. Pixels that weren't painted yet for the
+    /// previous image come first, continuing where painting left off.
+    /// Returns whether the previous image was being painted, but not completely.
+    fn set_image(&mut self, image: &View, generation: usize, all: bool) -> bool {
+        let dropped = self.passes > 0 && !self.done;
+        let changed = self
+            .image
+            .as_ref()
+            .map(|previous| changed_pixels(previous, image, self, all));
+
+        self.image = Some(image.clone());
+        self.generation = generation;
+        self.changed = changed;
+        self.done = false;
+        self.painted = 0;
+        self.commands = None;
+        self.changed_commands = None;
+        self.passes = 0;
+        dropped
+    }
 }
 
 impl Painter {
     /// Create a new painter.
+    ///
+    /// The painter paints the given regions, which must be within the area.
+    /// Images set on the painter must be views into the area.
+    /// With a work queue for the same area, the chunks taken from it are painted instead.
     pub fn new(
-        client: Option<Client>,
         area: Rect,
+        regions: &[Rect],
         offset: (i16, i16),
-        image: Option<DynamicImage>,
+        config: PainterConfig,
+        damage: Option<Arc<Damage>>,
+        queue: Option<Arc<WorkQueue>>,
+        stats: Arc<Stats>,
     ) -> Painter {
+        // Paint the own regions as a single chunk, unless taking chunks from a shared queue
+        let queue = queue.unwrap_or_else(|| {
+            // Mask the pixels of the regions, rather than testing each pixel against all regions
+            let width = area.w as usize;
+            let mut mask = vec![false; width * area.h as usize];
+            for region in regions {
+                let x = (region.x - area.x) as usize;
+                for y in region.y - area.y..region.y - area.y + region.h {
+                    let row = y as usize * width + x;
+                    mask[row..row + region.w as usize].fill(true);
+                }
+            }
+
+            let pixels = config
+                .order
+                .pixels(area.w, area.h, config.seed(area))
+                .into_iter()
+                .filter(|&(x, y)| mask[y as usize * width + x as usize])
+                .collect();
+            Arc::new(WorkQueue::from_chunks(vec![Chunk::new(pixels)]))
+        });
+
         Painter {
-            client,
+            client: None,
             area,
             offset,
-            image,
+            image: None,
+            generation: 0,
+            dropped: 0,
+            queue,
+            refresh: config.refresh,
+            damage,
+            stats,
+            stopped: false,
         }
     }
 
     /// Perform work.
-    /// Paint the whole defined area.
-    pub fn work(&mut self, img_receiver: &Receiver<DynamicImage>) -> Result<(), Error> {
+    /// Paint the whole defined area, or the next chunk from the work queue.
+    pub fn work(&mut self, img_receiver: &Receiver<View>) -> Result<(), Error> {
         // Wait for an image, if no image has been set yet
         if self.image.is_none() {
             // Show a warning
-            println!("Painter thread is waiting for an image...");
+            debug!("Painter is waiting for an image...");
 
             // Sleep a little
             // TODO: Do a proper error return here
             match img_receiver.recv() {
                 Ok(image) => self.set_image(image),
-                Err(_) => return Ok(()),
+                Err(_) => {
+                    self.stopped = true;
+                    return Ok(());
+                }
             }
 
             // We may now continue
-            println!("Painter thread received an image, painting...");
+            debug!("Painter received an image, painting...");
         }
 
-        // Get an RGB image
-        let image = self.image.as_mut().unwrap().to_rgba8();
+        // Update the image to paint, stop when asked to
+        self.receive(img_receiver);
+        if self.stopped || self.client.is_none() {
+            return Ok(());
+        }
 
-        // Loop through all the pixels, and set their color
-        for x in 0..self.area.w {
-            for y in 0..self.area.h {
-                // Update the image to paint
-                if let Ok(image) = img_receiver.try_recv() {
-                    self.set_image(image);
-                }
+        // Repaint damaged pixels first
+        self.repair()?;
 
-                // Get the pixel at this location
-                let pixel = image.get_pixel(x as u32, y as u32);
+        // Paint the next chunk, always put it back for other painters
+        let queue = self.queue.clone();
+        let index = queue.pop();
+        let result = self.paint(queue.chunk(index), img_receiver);
+        queue.push(index);
+        result
+    }
 
-                // Get the channels
-                let channels = pixel.channels();
+    /// Paint the given chunk.
+    fn paint(&mut self, chunk: &Mutex<Chunk>, img_receiver: &Receiver<View>) -> Result<(), Error> {
+        let (Some(client), Some(image)) = (&self.client, &self.image) else {
+            return Ok(());
+        };
+        let mut chunk = chunk.lock().unwrap();
 
-                if channels[3] == 0 {
-                    continue;
-                }
+        // Switch to the last image, unless another painter sharing the chunk already did
+        if chunk.generation < self.generation {
+            let previous = chunk.generation;
+            if chunk.set_image(image, self.generation, self.refresh <= 1) && previous > self.dropped
+            {
+                self.dropped = previous;
+                self.stats.add_dropped(1);
+            }
+        }
+        let Some(image) = chunk.image.clone() else {
+            return Ok(());
+        };
 
-                // Define the color
-                let color = Color::from(channels[0], channels[1], channels[2], channels[3]);
+        // Render the commands for a new image
+        if chunk.commands.is_none() {
+            chunk.commands = Some(render(
+                client,
+                &image,
+                self.area,
+                self.offset,
+                chunk.pixels.iter().copied(),
+            ));
+            chunk.changed_commands = chunk.changed.as_ref().map(|changed| {
+                render(
+                    client,
+                    &image,
+                    self.area,
+                    self.offset,
+                    changed.iter().copied(),
+                )
+            });
+        }
 
-                let x_calculated: u16 = ((x + self.area.x) as i16 + self.offset.0)
-                    .try_into()
-                    .unwrap();
-                let y_calculated: u16 = ((y + self.area.y) as i16 + self.offset.1)
-                    .try_into()
-                    .unwrap();
+        // Paint only the changed pixels, unless it is time for a full refresh.
+        // A new image is always painted from the changed pixels first.
+        // The commands are borrowed, so they are kept for the next pass, even on error.
+        let chunk = &mut *chunk;
+        chunk.passes += 1;
+        let full = chunk.done && (self.refresh <= 1 || chunk.passes.is_multiple_of(self.refresh));
+        let (commands, changed) = match &chunk.changed_commands {
+            Some(commands) if !full => (commands, true),
+            _ => (chunk.commands.as_ref().unwrap(), false),
+        };
 
-                // Set the pixel
-                if let Some(client) = &mut self.client {
-                    client.write_pixel(x_calculated, y_calculated, color)?;
-                }
+        // Write all command batches
+        let mut complete = true;
+        for batch in commands {
+            // Switch to a new image right away, stop when asked to
+            if self.receive(img_receiver) || self.stopped {
+                complete = false;
+                break;
+            }
+
+            self.repair()?;
+            if let Some(client) = &mut self.client {
+                client.write_batch(&batch.data, batch.pixels)?;
+                self.stats.add_pixels(batch.pixels, batch.data.len() as u64);
+            }
+
+            // Remember how far the image got, to continue there on a new image.
+            // Only a pass over the pixels counted by `painted` moves it.
+            if !chunk.done && changed == chunk.changed.is_some() {
+                chunk.painted = batch.end;
             }
         }
 
+        chunk.done |= complete;
+
         // Everything seems to be ok
         Ok(())
     }
 
-    /// Update the image that should be painted
-    pub fn set_image(&mut self, image: DynamicImage) {
+    /// Repaint damaged pixels reported by the defender, most contested first.
+    fn repair(&mut self) -> Result<(), Error> {
+        let (Some(damage), Some(client), Some(image)) =
+            (&self.damage, &mut self.client, &self.image)
+        else {
+            return Ok(());
+        };
+
+        let pixels = damage.take(REPAIR_COUNT);
+        if pixels.is_empty() {
+            return Ok(());
+        }
+        let area = self.area;
+        let pixels = pixels.into_iter().map(|(x, y)| (x - area.x, y - area.y));
+        for batch in render(client, image, self.area, self.offset, pixels) {
+            client.write_batch(&batch.data, batch.pixels)?;
+            self.stats.add_pixels(batch.pixels, batch.data.len() as u64);
+        }
+        Ok(())
+    }
+
+    /// Receive an image update, if there is any.
+    ///
+    /// Images queued up are skipped, and counted as dropped, only the last one is set.
+    /// Returns whether a new image was set.
+    /// The painter is stopped if the image channel is closed.
+    pub fn receive(&mut self, img_receiver: &Receiver<View>) -> bool {
+        let mut image = None;
+        loop {
+            match img_receiver.try_recv() {
+                Ok(next) => {
+                    if image.replace(next).is_some() {
+                        self.generation += 1;
+                        self.stats.add_dropped(1);
+                    }
+                }
+                Err(TryRecvError::Disconnected) => {
+                    self.stopped = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        match image {
+            Some(image) => {
+                self.set_image(image);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the painter has been stopped, because its image channel was closed.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Update the image that should be painted.
+    ///
+    /// Chunks switch to the image when they are painted next,
+    /// painting the pixels that changed first.
+    pub fn set_image(&mut self, image: View) {
         self.image = Some(image);
+        self.generation += 1;
     }
 
     /// Update the client.
-    pub fn set_client(&mut self, client: Option<Client>) {
+    ///
+    /// Flushes of the client are counted in the statistics of this painter.
+    pub fn set_client(&mut self, mut client: Option<Client>) {
+        if let Some(client) = &mut client {
+            client.set_stats(self.stats.clone());
+        }
         self.client = client;
     }
 }
+
+/// Render the commands to paint the given pixels of the image, in batches.
+///
+/// Pixel coordinates are relative to the area. Transparent pixels are skipped.
+fn render(
+    client: &Client,
+    image: &View,
+    area: Rect,
+    offset: (i16, i16),
+    pixels: impl Iterator<Item = (u16, u16)>,
+) -> Vec<Batch> {
+    let batch_size = client.batch_size();
+    let mut batches = Vec::new();
+    let mut batch = Batch {
+        data: Vec::with_capacity(batch_size),
+        pixels: 0,
+        end: 0,
+    };
+
+    for (i, (x, y)) in pixels.enumerate() {
+        // Get the pixel at this location
+        let pixel = image.get_pixel(x, y);
+
+        // Get the channels
+        let channels = pixel.channels();
+
+        if channels[3] == 0 {
+            continue;
+        }
+
+        // Define the color
+        let color = Color::from(channels[0], channels[1], channels[2], channels[3]);
+
+        // Skip pixels that don't fall on the screen
+        let (Ok(x_calculated), Ok(y_calculated)) = (
+            u16::try_from((x + area.x) as i32 + offset.0 as i32),
+            u16::try_from((y + area.y) as i32 + offset.1 as i32),
+        ) else {
+            continue;
+        };
+
+        // Start a new batch if this command may not fit
+        if batch.data.len() + client.max_command_size() > batch_size {
+            batches.push(std::mem::replace(
+                &mut batch,
+                Batch {
+                    data: Vec::with_capacity(batch_size),
+                    pixels: 0,
+                    end: 0,
+                },
+            ));
+        }
+        client.encode_pixel(&mut batch.data, x_calculated, y_calculated, color);
+        batch.pixels += 1;
+        batch.end = i + 1;
+    }
+
+    if batch.pixels > 0 {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Determine the pixels of a chunk to paint when switching from the previous to the next image.
+///
+/// Pixels not painted yet for the previous image come first, continuing the interrupted pass.
+/// They are followed by the other pixels that differ, or all other pixels if `all` is set,
+/// in paint order. Fully transparent pixels are skipped, as they are never painted.
+fn changed_pixels(previous: &View, next: &View, chunk: &Chunk, all: bool) -> Vec<(u16, u16)> {
+    // Mark the unpainted pixels within the bounds of the chunk
+    let unpainted = chunk.unpainted();
+    let bounds = chunk.bounds;
+    let index =
+        |(x, y): (u16, u16)| (y - bounds.y) as usize * bounds.w as usize + (x - bounds.x) as usize;
+    let mut pending = vec![false; bounds.w as usize * bounds.h as usize];
+    for &pixel in unpainted {
+        pending[index(pixel)] = true;
+    }
+
+    // Nothing differs if the same frame is shown again
+    let same = previous.same(next);
+
+    unpainted
+        .iter()
+        .copied()
+        .chain(
+            chunk
+                .pixels
+                .iter()
+                .copied()
+                .filter(|&pixel| !pending[index(pixel)])
+                .filter(|&(x, y)| {
+                    all || (!same && next.get_pixel(x, y) != previous.get_pixel(x, y))
+                }),
+        )
+        .filter(|&(x, y)| next.get_pixel(x, y)[3] != 0)
+        .collect()
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+use crate::painter::painter::{Chunk, PainterConfig};
+use crate::rect::Rect;
+
+/// A shared queue of chunks to paint.
+///
+/// Painters take a chunk from the front, paint it, and put it back at the end.
+/// This keeps all painters busy, no matter how long each chunk takes to paint.
+///
+/// The chunks hold their pixel order, rendered commands and paint progress,
+/// so these are kept once, no matter how many painters share the queue.
+pub struct WorkQueue {
+    /// All chunks, with the state of painting them.
+    chunks: Vec<Mutex<Chunk>>,
+
+    /// Indices of chunks that are waiting to be painted.
+    queue: Mutex<VecDeque<usize>>,
+
+    /// Notified when a chunk is put back.
+    available: Condvar,
+}
+
+impl WorkQueue {
+    /// Create a work queue, dividing the given area of the canvas into square chunks.
+    ///
+    /// The pixels of each chunk are ordered as configured, relative to the area.
+    pub fn new(area: Rect, config: &PainterConfig) -> WorkQueue {
+        let chunk_size = config.chunk_size.max(1);
+        let seed = config.seed(area);
+        let chunks = (0..area.h)
+            .step_by(chunk_size as usize)
+            .flat_map(|y| {
+                (0..area.w).step_by(chunk_size as usize).map(move |x| {
+                    Rect::from(x, y, chunk_size.min(area.w - x), chunk_size.min(area.h - y))
+                })
+            })
+            .map(|chunk| {
+                Chunk::new(
+                    config
+                        .order
+                        .pixels(chunk.w, chunk.h, seed)
+                        .into_iter()
+                        .map(|(x, y)| (x + chunk.x, y + chunk.y))
+                        .collect(),
+                )
+            })
+            .collect();
+
+        WorkQueue::from_chunks(chunks)
+    }
+
+    /// Create a work queue of the given chunks.
+    pub fn from_chunks(chunks: Vec<Chunk>) -> WorkQueue {
+        WorkQueue {
+            queue: Mutex::new((0..chunks.len()).collect()),
+            chunks: chunks.into_iter().map(Mutex::new).collect(),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Get the chunk with the given index.
+    ///
+    /// Only the painter that took the chunk from the queue should lock it.
+    pub fn chunk(&self, index: usize) -> &Mutex<Chunk> {
+        &self.chunks[index]
+    }
+
+    /// Take the next chunk to paint, waiting until one is available.
+    pub fn pop(&self) -> usize {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(index) = queue.pop_front() {
+                return index;
+            }
+            queue = self.available.wait(queue).unwrap();
+        }
+    }
+
+    /// Put back a chunk, after painting it.
+    pub fn push(&self, index: usize) {
+        self.queue.lock().unwrap().push_back(index);
+        self.available.notify_one();
+    }
+}
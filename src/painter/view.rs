@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use image::{Rgba, RgbaImage};
+
+use crate::rect::Rect;
+
+/// A view into an area of a frame shared by all painters.
+///
+/// Painters each paint their own area of the same frame,
+/// so frames are never copied for each painter.
+#[derive(Clone)]
+pub struct View {
+    frame: Arc<RgbaImage>,
+    area: Rect,
+}
+
+impl View {
+    /// Create a view into the given area of a frame.
+    ///
+    /// The area must be within the frame.
+    pub fn new(frame: Arc<RgbaImage>, area: Rect) -> View {
+        View { frame, area }
+    }
+
+    /// Get the pixel at the given position, relative to the area.
+    pub fn get_pixel(&self, x: u16, y: u16) -> &Rgba<u8> {
+        self.frame
+            .get_pixel((x + self.area.x) as u32, (y + self.area.y) as u32)
+    }
+
+    /// Whether both views show the same area of the same frame.
+    pub fn same(&self, other: &View) -> bool {
+        Arc::ptr_eq(&self.frame, &other.frame)
+            && (self.area.x, self.area.y, self.area.w, self.area.h)
+                == (other.area.x, other.area.y, other.area.w, other.area.h)
+    }
+}
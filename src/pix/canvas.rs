@@ -1,118 +1,412 @@
+use std::io::Error;
+use std::process;
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
-use std::thread::sleep;
-use std::time::Duration;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use image::DynamicImage;
+use image::RgbaImage;
+use log::{debug, error, info, warn};
 
+use crate::layout::Layout;
+use crate::logger::warn_limited;
+use crate::painter::damage::Damage;
+use crate::painter::defender::Defender;
 use crate::painter::handle::Handle;
-use crate::painter::painter::Painter;
-use crate::pix::client::Client;
+use crate::painter::painter::{Painter, PainterConfig};
+use crate::painter::queue::WorkQueue;
+use crate::painter::view::View;
+use crate::pix::client::{Client, ClientConfig};
+use crate::pix::limit::RateLimiter;
+use crate::pix::reconnect::CircuitBreaker;
 use crate::rect::Rect;
+use crate::stats::Stats;
+
+// The time a connection must stay up, to count as successful and reset the backoff.
+const STABLE_CONNECTION: Duration = Duration::from_secs(1);
 
 /// A pixflut instance
 pub struct Canvas {
     host: String,
     painter_count: usize,
     painter_handles: Vec<Handle>,
-    size: (u16, u16),
     offset: (i16, i16),
+
+    /// The part of the canvas that is visible on the screen, in canvas coordinates.
+    visible: Rect,
+
+    painter_config: PainterConfig,
+    defender: Option<(JoinHandle<()>, Sender<Arc<RgbaImage>>)>,
+
+    /// The circuit breaker shared by all connections to the host.
+    breaker: Arc<CircuitBreaker>,
+
+    /// The rate limiter shared by all painters, if globally rate limited.
+    limiter: Option<Arc<RateLimiter>>,
 }
 
 impl Canvas {
     /// Create a new pixelflut canvas.
+    ///
+    /// Pixels that don't fall on the screen are never painted.
+    /// If the screen size is unknown, only pixels at negative positions are skipped.
     pub fn new(
         host: &str,
         painter_count: usize,
         size: (u16, u16),
         offset: (i16, i16),
-        binary: bool,
-        flush: bool,
+        screen: Option<(u16, u16)>,
+        config: ClientConfig,
+        painter_config: PainterConfig,
     ) -> Canvas {
         // Initialize the object
         let mut canvas = Canvas {
             host: host.to_string(),
             painter_count,
             painter_handles: Vec::with_capacity(painter_count),
-            size,
             offset,
+            visible: visible_area(size, offset, screen),
+            painter_config,
+            defender: None,
+            breaker: Arc::new(CircuitBreaker::new(config.reconnect)),
+            limiter: config
+                .rate_limit
+                .global
+                .map(|rate| Arc::new(RateLimiter::new(rate, config.rate_limit.unit))),
         };
 
         // Show a status message
-        println!("Starting painter threads...");
+        if canvas.visible.w == 0 || canvas.visible.h == 0 {
+            warn!("Image is entirely off-screen, nothing to paint");
+        }
+        info!("Starting painter threads...");
 
         // Spawn some painters
-        canvas.spawn_painters(binary, flush);
+        canvas.spawn_painters(config);
+
+        // Spawn the defender
+        if painter_config.defend {
+            info!("Starting defender thread...");
+            canvas.spawn_defender(config);
+        }
 
         // Return the canvas
         canvas
     }
 
     /// Spawn the painters for this canvas
-    fn spawn_painters(&mut self, binary: bool, flush: bool) {
-        // Spawn some painters
-        for i in 0..self.painter_count {
-            // Determine the slice width
-            let width = self.size.0 / (self.painter_count as u16);
+    fn spawn_painters(&mut self, config: ClientConfig) {
+        // When stealing work, all painters share a queue of chunks, and the damage
+        let layout = self.painter_config.layout;
+        let defend = self.painter_config.defend;
+        let steal = layout == Layout::Steal;
+        let queue = steal.then(|| Arc::new(WorkQueue::new(self.visible, &self.painter_config)));
+        let shared_damage = (steal && defend).then(|| Arc::new(Damage::default()));
 
-            // Define the area to paint per thread
-            let painter_area = Rect::from((i as u16) * width, 0, width, self.size.1);
+        // Divide the visible canvas into the regions to paint per thread
+        let visible = self.visible;
+        for regions in layout.regions((visible.w, visible.h), self.painter_count) {
+            let regions = regions
+                .into_iter()
+                .map(|r| Rect::from(r.x + visible.x, r.y + visible.y, r.w, r.h))
+                .collect();
 
             // Spawn the painter
-            self.spawn_painter(painter_area, binary, flush);
+            let damage = shared_damage
+                .clone()
+                .or_else(|| defend.then(|| Arc::new(Damage::default())));
+            self.spawn_painter(regions, config, damage, queue.clone());
         }
     }
 
-    /// Spawn a single painter in a thread.
-    fn spawn_painter(&mut self, area: Rect, binary: bool, flush: bool) {
+    /// Spawn a single painter in a thread, painting the given regions.
+    fn spawn_painter(
+        &mut self,
+        regions: Vec<Rect>,
+        config: ClientConfig,
+        damage: Option<Arc<Damage>>,
+        queue: Option<Arc<WorkQueue>>,
+    ) {
+        // The painter receives the image within the bounds of its regions
+        let area = Rect::bounds(&regions);
+        let painter_regions = regions.clone();
+
         // Get the host that will be used
         let host = self.host.to_string();
 
         // Redefine the offset to make it usable in the thread
         let offset = (self.offset.0, self.offset.1);
+        let painter_config = self.painter_config;
+        let painter_damage = damage.clone();
+        let stats = Arc::new(Stats::default());
+        let painter_stats = stats.clone();
+        let breaker = self.breaker.clone();
+
+        // Limit the rate of this painter first, then take from the shared limit
+        let rate_limit = config.rate_limit;
+        let limiters: Vec<Arc<RateLimiter>> = rate_limit
+            .connection
+            .map(|rate| Arc::new(RateLimiter::new(rate, rate_limit.unit)))
+            .into_iter()
+            .chain(self.limiter.clone())
+            .collect();
 
         // Create a channel to push new images
-        let (tx, rx): (Sender<DynamicImage>, Receiver<DynamicImage>) = mpsc::channel();
+        let (tx, rx): (Sender<View>, Receiver<View>) = mpsc::channel();
 
-        // Create the painter thread
-        let thread = thread::spawn(move || {
-            // Create the painter
-            let mut painter = Painter::new(None, area, offset, None);
+        // Create the painter thread, named to identify the painter in log messages
+        let name = format!("painter-{}", self.painter_handles.len());
+        let thread = thread::Builder::new()
+            .name(name)
+            .spawn(move || {
+                // Create the painter
+                let mut painter = Painter::new(
+                    area,
+                    &painter_regions,
+                    offset,
+                    painter_config,
+                    painter_damage,
+                    queue,
+                    painter_stats.clone(),
+                );
 
-            loop {
-                // Connect
-                match Client::connect(host.clone(), binary, flush) {
-                    Ok(client) => {
+                // Keep painting, reconnecting when the connection fails
+                keep_connected(
+                    &mut painter,
+                    "Painter",
+                    &breaker,
+                    Some(&painter_stats),
+                    |painter| {
+                        let mut client = Client::connect(host.clone(), &config)?;
+                        client.set_limiters(limiters.clone());
                         painter.set_client(Some(client));
+                        Ok(())
+                    },
+                    |painter| {
+                        painter.receive(&rx);
+                        painter.is_stopped()
+                    },
+                    |painter| painter.work(&rx),
+                );
 
-                        // Keep painting
-                        loop {
-                            if let Err(e) = painter.work(&rx) {
-                                println!("Painter error: {}", e);
-                                break;
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Painter failed to connect: {}", e);
-                    }
-                };
+                // Nicely disconnect
+                painter.set_client(None);
+            })
+            .expect("Failed to spawn painter thread");
 
-                // Sleep for half a second before restarting the painter
-                sleep(Duration::from_millis(500));
-                println!("Restarting failed painter...");
-            }
+        // Create a new painter handle, pust it to the list
+        self.painter_handles
+            .push(Handle::new(thread, area, regions, tx, damage, stats));
+    }
+
+    /// Spawn the defender in a thread.
+    ///
+    /// The defender reads back the canvas over a separate connection,
+    /// and reports overwritten pixels to the painters owning them.
+    fn spawn_defender(&mut self, config: ClientConfig) {
+        let host = self.host.to_string();
+        let offset = self.offset;
+        let targets: Vec<(Rect, Arc<Damage>)> = self
+            .painter_handles
+            .iter()
+            .filter_map(|handle| Some((handle.regions(), handle.damage()?)))
+            .flat_map(|(regions, damage)| {
+                regions
+                    .iter()
+                    .map(move |&region| (region, damage.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let breaker = self.breaker.clone();
+
+        // Create a channel to push new images
+        let (tx, rx): (Sender<Arc<RgbaImage>>, Receiver<Arc<RgbaImage>>) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name("defender".into())
+            .spawn(move || {
+                let mut defender = Defender::new(offset, targets);
+
+                // Keep defending, reading back the canvas requires TCP
+                keep_connected(
+                    &mut defender,
+                    "Defender",
+                    &breaker,
+                    None,
+                    |defender| {
+                        defender.set_client(Some(Client::connect_tcp(&host, &config)?));
+                        Ok(())
+                    },
+                    |defender| {
+                        defender.receive(&rx);
+                        defender.is_stopped()
+                    },
+                    |defender| defender.work(&rx),
+                );
+
+                // Nicely disconnect
+                defender.set_client(None);
+            })
+            .expect("Failed to spawn defender thread");
+
+        self.defender = Some((thread, tx));
+    }
+
+    /// Get the statistics of all painters.
+    pub fn stats(&self) -> Vec<Arc<Stats>> {
+        self.painter_handles
+            .iter()
+            .map(|handle| handle.stats())
+            .collect()
+    }
+
+    /// Stop all painters and the defender, and wait for them to disconnect.
+    ///
+    /// Returns the statistics of all painters.
+    pub fn stop(self) -> Vec<Arc<Stats>> {
+        let stats = self.stats();
+
+        // Close all image channels first, so all threads stop at the same time
+        let defender = self.defender.map(|(thread, tx)| {
+            drop(tx);
+            thread
         });
+        let painters: Vec<_> = self
+            .painter_handles
+            .into_iter()
+            .map(|handle| handle.stop())
+            .collect();
 
-        // Create a new painter handle, pust it to the list
-        self.painter_handles.push(Handle::new(thread, area, tx));
+        // Wait for all of them to disconnect
+        if defender.is_some_and(|thread| thread.join().is_err()) {
+            error!("Defender thread panicked");
+        }
+        for thread in painters {
+            if thread.join().is_err() {
+                error!("Painter thread panicked");
+            }
+        }
+
+        stats
     }
 
     // Update the image that is being rendered for all painters.
-    pub fn update_image(&mut self, image: &mut DynamicImage) {
+    pub fn update_image(&mut self, frame: &Arc<RgbaImage>) {
         // Update the image for each specific painter handle
         for handle in &self.painter_handles {
-            handle.update_image(image);
+            handle.update_image(frame);
+        }
+
+        // Update the image to defend
+        if let Some((_, defender)) = &self.defender {
+            defender
+                .send(frame.clone())
+                .expect("Failed to send image update to defender");
         }
     }
 }
+
+/// Keep a painter or defender connected to the host, until it is stopped.
+///
+/// Whenever the circuit breaker allows it, `connect` gives the worker a client, and `work`
+/// is called until stopped or failing. Failures are logged and counted, and connecting
+/// is retried after backing off, giving up after too many failures in a row.
+/// `stopped` receives image updates, and tells whether the worker stopped.
+fn keep_connected<T>(
+    worker: &mut T,
+    name: &'static str,
+    breaker: &CircuitBreaker,
+    stats: Option<&Stats>,
+    mut connect: impl FnMut(&mut T) -> Result<(), Error>,
+    mut stopped: impl FnMut(&mut T) -> bool,
+    mut work: impl FnMut(&mut T) -> Result<(), Error>,
+) {
+    loop {
+        // Wait until the circuit breaker allows connecting, unless stopped
+        if !breaker.acquire(&mut || stopped(worker)) {
+            break;
+        }
+
+        // Connect, and keep working until stopped
+        match connect(worker) {
+            Ok(()) => {
+                if let Err(e) = work_connected(worker, breaker, &mut stopped, &mut work) {
+                    warn_limited!(name, "{} error: {}", name, e);
+                    if let Some(stats) = stats {
+                        stats.add_error();
+                    }
+                }
+            }
+            Err(e) => {
+                warn_limited!(name, "{} failed to connect: {}", name, e);
+                if let Some(stats) = stats {
+                    stats.add_error();
+                }
+            }
+        };
+        if stopped(worker) {
+            break;
+        }
+
+        // Back off before reconnecting, give up after too many retries
+        if !breaker.failure() {
+            give_up(breaker.failures());
+        }
+        debug!("Restarting failed {}...", name.to_lowercase());
+        if let Some(stats) = stats {
+            stats.add_reconnect();
+        }
+    }
+}
+
+/// Keep working on a new connection, until stopped or failing.
+///
+/// Servers may accept connections and drop them right away, to ban clients.
+/// The circuit breaker is therefore only closed once the connection stayed up for a while.
+fn work_connected<T>(
+    worker: &mut T,
+    breaker: &CircuitBreaker,
+    stopped: &mut impl FnMut(&mut T) -> bool,
+    work: &mut impl FnMut(&mut T) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let connected = Instant::now();
+    let mut proven = false;
+    while !stopped(worker) {
+        work(worker)?;
+        if !proven && connected.elapsed() >= STABLE_CONNECTION {
+            breaker.success();
+            proven = true;
+        }
+    }
+    Ok(())
+}
+
+/// Give up reconnecting to the host, and quit with a non-zero exit code.
+fn give_up(failures: u32) -> ! {
+    error!(
+        "Giving up after {} failed connection attempt(s) in a row",
+        failures
+    );
+    process::exit(1);
+}
+
+/// Determine the part of a canvas that is visible on the screen, in canvas coordinates.
+///
+/// Without a known screen size, everything at a non-negative position is visible.
+fn visible_area(size: (u16, u16), offset: (i16, i16), screen: Option<(u16, u16)>) -> Rect {
+    let screen = screen.map_or((u16::MAX as i32 + 1, u16::MAX as i32 + 1), |(w, h)| {
+        (w as i32, h as i32)
+    });
+
+    // Clamp both edges of each axis to the screen
+    let axis = |size: u16, offset: i16, screen: i32| {
+        let start = (-(offset as i32)).clamp(0, size as i32);
+        let end = (screen - offset as i32).clamp(start, size as i32);
+        (start as u16, (end - start) as u16)
+    };
+    let (x, w) = axis(size.0, offset.0, screen.0);
+    let (y, h) = axis(size.1, offset.1, screen.1);
+    Rect::from(x, y, w, h)
+}
@@ -1,11 +1,16 @@
 use std::io::prelude::*;
 use std::io::{Error, ErrorKind};
-use std::net::TcpStream;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 use bufstream::BufStream;
 use regex::Regex;
 
 use crate::color::Color;
+use crate::pix::limit::{RateLimitConfig, RateLimiter};
+use crate::pix::reconnect::ReconnectConfig;
+use crate::stats::Stats;
 
 // The default buffer size for reading the client stream.
 // - Big enough so we don't have to expand
@@ -15,6 +20,155 @@ const CMD_READ_BUFFER_SIZE: usize = 32;
 // The response format of the screen size from a pixelflut server.
 const PIX_SERVER_SIZE_REGEX: &str = r"^(?i)\s*SIZE\s+([[:digit:]]+)\s+([[:digit:]]+)\s*$";
 
+// The response format of a pixel color from a pixelflut server.
+const PIX_SERVER_PIXEL_REGEX: &str =
+    r"^(?i)\s*PX\s+([[:digit:]]+)\s+([[:digit:]]+)\s+([[:xdigit:]]{6}|[[:xdigit:]]{8})\s*$";
+
+// The size of a binary `PB` pixel command.
+const BINARY_COMMAND_SIZE: usize = 10;
+
+// The maximum size of a text `PX x y rrggbbaa` pixel command, including newline.
+const MAX_TEXT_COMMAND_SIZE: usize = 26;
+
+// The preferred size of a batch of pixel commands written to a TCP stream.
+const TCP_BATCH_SIZE: usize = 64 * 1024;
+
+// The host prefix to select the UDP transport.
+const UDP_HOST_PREFIX: &str = "udp://";
+
+// The host prefix to explicitly select the TCP transport.
+const TCP_HOST_PREFIX: &str = "tcp://";
+
+// The default maximum payload size of a single UDP datagram.
+// This fits a default 1500 byte ethernet MTU, minus the IP and UDP headers.
+pub const DEFAULT_UDP_MTU: usize = 1472;
+
+// The minimum payload size of a single UDP datagram, fitting any pixel command.
+pub const MIN_UDP_MTU: usize = MAX_TEXT_COMMAND_SIZE;
+
+// The default size of the read and write buffers of a TCP stream.
+// This fits a whole batch, so batches aren't made smaller to fit the buffer.
+pub const DEFAULT_BUFFER_SIZE: usize = TCP_BATCH_SIZE;
+
+// The default timeouts in milliseconds, for connecting, reading a reply and writing.
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 5000;
+pub const DEFAULT_READ_TIMEOUT: u64 = 5000;
+pub const DEFAULT_WRITE_TIMEOUT: u64 = 10000;
+
+/// Configuration for creating pixelflut clients.
+#[derive(Copy, Clone)]
+pub struct ClientConfig {
+    /// Whether to use binary mode (PB) instead of (PX).
+    pub binary: bool,
+
+    /// When to flush the stream.
+    pub flush: FlushPolicy,
+
+    /// The size of the write buffer of a TCP stream.
+    pub buffer_size: usize,
+
+    /// Whether to use UDP instead of TCP.
+    /// A host with an `udp://` prefix always uses UDP.
+    pub udp: bool,
+
+    /// The maximum payload size of a UDP datagram.
+    pub mtu: usize,
+
+    /// The policy for reconnecting after a connection failed.
+    pub reconnect: ReconnectConfig,
+
+    /// The timeouts for connecting, reading and writing.
+    pub timeouts: Timeouts,
+
+    /// The rate limits for sending pixels.
+    pub rate_limit: RateLimitConfig,
+}
+
+/// When to flush a TCP stream, to actually send buffered commands.
+///
+/// If any of the thresholds is set, the stream is flushed as soon as one is reached.
+/// Otherwise the stream is flushed after each batch if `batch` is set, or only
+/// when the write buffer is full.
+#[derive(Copy, Clone, Default)]
+pub struct FlushPolicy {
+    /// Flush after each batch of pixels.
+    pub batch: bool,
+
+    /// Flush after this many commands.
+    pub commands: Option<u64>,
+
+    /// Flush after this many bytes.
+    pub bytes: Option<u64>,
+
+    /// Flush after this much time passed since the last flush.
+    pub interval: Option<Duration>,
+}
+
+impl FlushPolicy {
+    /// Whether to flush, after writing the given number of commands and bytes
+    /// since the last flush.
+    fn is_due(&self, commands: u64, bytes: u64, last_flush: Instant) -> bool {
+        if self.commands.is_none() && self.bytes.is_none() && self.interval.is_none() {
+            return self.batch;
+        }
+        self.commands.is_some_and(|max| commands >= max)
+            || self.bytes.is_some_and(|max| bytes >= max)
+            || self
+                .interval
+                .is_some_and(|interval| last_flush.elapsed() >= interval)
+    }
+}
+
+/// Timeouts for a connection to a pixelflut server, `None` to wait forever.
+#[derive(Copy, Clone)]
+pub struct Timeouts {
+    /// The timeout for connecting.
+    pub connect: Option<Duration>,
+
+    /// The timeout for reading a reply.
+    pub read: Option<Duration>,
+
+    /// The timeout for writing commands.
+    pub write: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: Some(Duration::from_millis(DEFAULT_CONNECT_TIMEOUT)),
+            read: Some(Duration::from_millis(DEFAULT_READ_TIMEOUT)),
+            write: Some(Duration::from_millis(DEFAULT_WRITE_TIMEOUT)),
+        }
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            binary: false,
+            flush: FlushPolicy {
+                batch: true,
+                ..FlushPolicy::default()
+            },
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            udp: false,
+            mtu: DEFAULT_UDP_MTU,
+            reconnect: ReconnectConfig::default(),
+            timeouts: Timeouts::default(),
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+/// The stream a client talks through.
+enum Stream {
+    /// A buffered TCP stream.
+    Tcp(BufStream<TcpStream>),
+
+    /// A UDP socket, packing commands into datagrams.
+    Udp(DatagramWriter),
+}
+
 /// A pixelflut client.
 ///
 /// This client uses a stream to talk to a pixelflut panel.
@@ -23,33 +177,126 @@ const PIX_SERVER_SIZE_REGEX: &str = r"^(?i)\s*SIZE\s+([[:digit:]]+)\s+([[:digit:
 /// The client provides an interface for other logic to easily talk
 /// to the pixelflut panel.
 pub struct Client {
-    stream: BufStream<TcpStream>,
+    stream: Stream,
 
     /// Whether to use binary mode (PB) instead of (PX).
     binary: bool,
 
-    /// Whether to flush the stream after each pixel.
-    flush: bool,
+    /// When to flush the stream.
+    flush: FlushPolicy,
+
+    /// The size of the write buffer of a TCP stream.
+    buffer_size: usize,
+
+    /// The number of commands written since the last flush.
+    unflushed_commands: u64,
+
+    /// The number of bytes written since the last flush.
+    unflushed_bytes: u64,
+
+    /// When the stream was last flushed.
+    last_flush: Instant,
+
+    /// Statistics to count flushes in.
+    stats: Option<Arc<Stats>>,
+
+    /// Rate limiters to take from before writing pixels.
+    limiters: Vec<Arc<RateLimiter>>,
+
+    /// The timeouts used for connections made by this client.
+    timeouts: Timeouts,
 }
 
 impl Client {
-    /// Create a new client instance.
-    pub fn new(stream: TcpStream, binary: bool, flush: bool) -> Client {
+    /// Create a new client instance, with a write buffer of the given size.
+    pub fn new(stream: TcpStream, binary: bool, flush: FlushPolicy, buffer_size: usize) -> Client {
         Client {
-            stream: BufStream::new(stream),
+            stream: Stream::Tcp(BufStream::with_capacities(
+                DEFAULT_BUFFER_SIZE,
+                buffer_size,
+                stream,
+            )),
             binary,
             flush,
+            buffer_size,
+            unflushed_commands: 0,
+            unflushed_bytes: 0,
+            last_flush: Instant::now(),
+            stats: None,
+            limiters: Vec::new(),
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    /// Create a new client instance sending datagrams through the given connected socket.
+    ///
+    /// Commands are packed into datagrams of at most `mtu` bytes.
+    pub fn new_udp(socket: UdpSocket, binary: bool, mtu: usize) -> Client {
+        Client {
+            stream: Stream::Udp(DatagramWriter::new(socket, mtu)),
+            binary,
+            flush: FlushPolicy::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            unflushed_commands: 0,
+            unflushed_bytes: 0,
+            last_flush: Instant::now(),
+            stats: None,
+            limiters: Vec::new(),
+            timeouts: Timeouts::default(),
         }
     }
 
     /// Create a new client instane from the given host, and connect to it.
-    pub fn connect(host: String, binary: bool, flush: bool) -> Result<Client, Error> {
-        // Create a new stream, and instantiate the client
-        Ok(Client::new(create_stream(host)?, binary, flush))
+    ///
+    /// The host may be prefixed with `udp://` or `tcp://` to select the transport,
+    /// overriding the transport from the configuration.
+    pub fn connect(host: String, config: &ClientConfig) -> Result<Client, Error> {
+        // Select the transport, and create a client with a matching stream
+        let (host, udp) = parse_host(&host, config.udp);
+        let mut client = if udp {
+            Client::new_udp(
+                create_udp_socket(host, &config.timeouts)?,
+                config.binary,
+                config.mtu,
+            )
+        } else {
+            Client::new(
+                create_stream(host, &config.timeouts)?,
+                config.binary,
+                config.flush,
+                config.buffer_size,
+            )
+        };
+        client.timeouts = config.timeouts;
+        Ok(client)
+    }
+
+    /// Create a new client instance from the given host, and connect to it over TCP.
+    ///
+    /// A transport prefix of the host is ignored, as replies can only be read over TCP.
+    pub fn connect_tcp(host: &str, config: &ClientConfig) -> Result<Client, Error> {
+        let (host, _) = parse_host(host, false);
+        let config = ClientConfig {
+            udp: false,
+            ..*config
+        };
+        Client::connect(host.to_string(), &config)
     }
 
-    /// Write a pixel to the given stream.
-    pub fn write_pixel(&mut self, x: u16, y: u16, color: Color) -> Result<(), Error> {
+    /// Set the statistics to count flushes of the connection in.
+    pub fn set_stats(&mut self, stats: Arc<Stats>) {
+        self.stats = Some(stats);
+    }
+
+    /// Set the rate limiters to wait for before writing a batch of pixels.
+    pub fn set_limiters(&mut self, limiters: Vec<Arc<RateLimiter>>) {
+        self.limiters = limiters;
+    }
+
+    /// Encode the command to set a pixel, and append it to the given buffer.
+    ///
+    /// Encoded commands are written in batches using `write_batch`.
+    pub fn encode_pixel(&self, buffer: &mut Vec<u8>, x: u16, y: u16, color: Color) {
         if self.binary {
             let mut data = [
                 b'P', b'B',
@@ -63,55 +310,181 @@ impl Client {
             ];
             data[2..4].copy_from_slice(&x.to_le_bytes());
             data[4..6].copy_from_slice(&y.to_le_bytes());
-            self.write_command(&data, false)
+            buffer.extend_from_slice(&data);
         } else {
-            self.write_command(
-                format!("PX {} {} {}", x, y, color.as_hex()).as_bytes(),
-                true,
-            )
+            // Writing to a vector never fails
+            let _ = writeln!(buffer, "PX {} {} {}", x, y, color.as_hex());
         }
     }
 
+    /// The maximum size of a single encoded pixel command.
+    pub fn max_command_size(&self) -> usize {
+        if self.binary {
+            BINARY_COMMAND_SIZE
+        } else {
+            MAX_TEXT_COMMAND_SIZE
+        }
+    }
+
+    /// The preferred maximum size of a batch of encoded commands.
+    ///
+    /// With UDP, each batch is sent as a single datagram.
+    /// With TCP, a batch fits the write buffer, and doesn't exceed the flush thresholds.
+    /// When rate limited, a batch never exceeds the burst of a rate limiter.
+    /// A batch always fits at least a single command.
+    pub fn batch_size(&self) -> usize {
+        let command_size = self.max_command_size();
+        let size = match &self.stream {
+            Stream::Tcp(_) => {
+                // Keep batches small enough to flush when the thresholds are reached
+                let commands = self
+                    .flush
+                    .commands
+                    .map(|commands| commands as usize * command_size);
+                let bytes = self.flush.bytes.map(|bytes| bytes as usize);
+                [Some(self.buffer_size), commands, bytes]
+                    .into_iter()
+                    .flatten()
+                    .fold(TCP_BATCH_SIZE, usize::min)
+            }
+            Stream::Udp(writer) => writer.mtu,
+        };
+
+        // Keep batches small enough to spread them out evenly when rate limited
+        self.limiters
+            .iter()
+            .map(|limiter| limiter.max_batch_size(command_size))
+            .fold(size, usize::min)
+            .max(command_size)
+    }
+
+    /// Write a batch of encoded commands to the stream.
+    ///
+    /// The batch must only contain complete commands, `commands` is the number of commands in it.
+    /// The stream is flushed afterwards when due, following the flush policy.
+    pub fn write_batch(&mut self, batch: &[u8], commands: u64) -> Result<(), Error> {
+        // Wait until the rate limits allow writing this batch
+        for limiter in &self.limiters {
+            limiter.acquire(commands, batch.len() as u64);
+        }
+
+        self.write_command(batch, false)?;
+
+        // Flush, make sure to clear the send buffer
+        self.unflushed_commands += commands;
+        self.unflushed_bytes += batch.len() as u64;
+        if self.flush.is_due(
+            self.unflushed_commands,
+            self.unflushed_bytes,
+            self.last_flush,
+        ) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flush the stream, sending all buffered commands.
+    fn flush(&mut self) -> Result<(), Error> {
+        let Stream::Tcp(stream) = &mut self.stream else {
+            return Ok(());
+        };
+        stream.flush()?;
+        self.unflushed_commands = 0;
+        self.unflushed_bytes = 0;
+        self.last_flush = Instant::now();
+        if let Some(stats) = &self.stats {
+            stats.add_flush();
+        }
+        Ok(())
+    }
+
     /// Read the size of the screen.
+    ///
+    /// UDP gives no reply, so a UDP client queries the size over a separate TCP
+    /// connection to the same address.
     pub fn read_screen_size(&mut self) -> Result<(u16, u16), Error> {
+        // Fall back to a TCP query when using UDP
+        if let Stream::Udp(writer) = &self.stream {
+            let host = writer.socket.peer_addr()?.to_string();
+            let stream = create_stream(&host, &self.timeouts)?;
+            return Client::new(stream, false, FlushPolicy::default(), DEFAULT_BUFFER_SIZE)
+                .read_screen_size();
+        }
+
         // Read the screen size
         let data = self
             .write_read_command(b"SIZE")
-            .expect("Failed to read screen size");
+            .map_err(|e| match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => Error::new(
+                    ErrorKind::TimedOut,
+                    "Failed to read screen size, server did not reply to SIZE in time",
+                ),
+                _ => Error::new(e.kind(), format!("Failed to read screen size: {}", e)),
+            })?;
 
         // Build a regex to parse the screen size
         let re = Regex::new(PIX_SERVER_SIZE_REGEX).unwrap();
 
         // Find captures in the data, return the result
+        let malformed = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Failed to parse screen size, received malformed data: {:?}",
+                    data.trim_end()
+                ),
+            )
+        };
+        let matches = re.captures(&data).ok_or_else(malformed)?;
+        Ok((
+            matches[1].parse::<u16>().map_err(|_| malformed())?,
+            matches[2].parse::<u16>().map_err(|_| malformed())?,
+        ))
+    }
+
+    /// Read the color of a pixel on the screen.
+    ///
+    /// Both `PX x y rrggbb` and `PX x y rrggbbaa` replies are supported.
+    pub fn read_pixel(&mut self, x: u16, y: u16) -> Result<Color, Error> {
+        // Read the pixel
+        let data = self.write_read_command(format!("PX {} {}", x, y).as_bytes())?;
+
+        // Build a regex to parse the pixel, once as this is used a lot
+        static RE: OnceLock<Regex> = OnceLock::new();
+        let re = RE.get_or_init(|| Regex::new(PIX_SERVER_PIXEL_REGEX).unwrap());
+
+        // Find captures in the data, make sure it's the pixel we asked for
         match re.captures(&data) {
-            Some(matches) => Ok((
-                matches[1]
-                    .parse::<u16>()
-                    .expect("Failed to parse screen width, received malformed data"),
-                matches[2]
-                    .parse::<u16>()
-                    .expect("Failed to parse screen height, received malformed data"),
-            )),
-            None => Err(Error::new(
-                ErrorKind::Other,
-                "Failed to parse screen size, received malformed data",
+            Some(matches) if matches[1].parse() == Ok(x) && matches[2].parse() == Ok(y) => {
+                Color::from_hex(&matches[3]).ok_or_else(|| {
+                    Error::other("Failed to parse pixel color, received malformed data")
+                })
+            }
+            _ => Err(Error::other(
+                "Failed to parse pixel, received malformed data",
             )),
         }
     }
 
     /// Write the given command to the given stream.
     fn write_command(&mut self, cmd: &[u8], newline: bool) -> Result<(), Error> {
+        let stream = match &mut self.stream {
+            Stream::Tcp(stream) => stream,
+            Stream::Udp(writer) => {
+                // Count each datagram sent as a flush
+                if writer.write_command(cmd, newline)? {
+                    if let Some(stats) = &self.stats {
+                        stats.add_flush();
+                    }
+                }
+                return Ok(());
+            }
+        };
+
         // Write the pixels and a new line
-        self.stream.write_all(cmd)?;
+        stream.write_all(cmd)?;
         if newline {
-            self.stream.write_all(b"\n")?;
-        }
-
-        // Flush, make sure to clear the send buffer
-        // TODO: only flush each 100 pixels?
-        // TODO: make buffer size configurable?
-        if self.flush {
-            self.stream.flush()?;
+            stream.write_all(b"\n")?;
         }
 
         // Everything seems to be ok
@@ -120,16 +493,31 @@ impl Client {
 
     /// Write the given command to the given stream, and read the output.
     fn write_read_command(&mut self, cmd: &[u8]) -> Result<String, Error> {
+        let stream = match &mut self.stream {
+            Stream::Tcp(stream) => stream,
+            Stream::Udp(_) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Cannot read responses over UDP",
+                ))
+            }
+        };
+
         // Write the command
-        self.write_command(cmd, true)?;
+        stream.write_all(cmd)?;
+        stream.write_all(b"\n")?;
 
         // Flush the pipe, ensure the command is actually sent
-        self.stream.flush()?;
+        stream.flush()?;
 
-        // Read the output
-        // TODO: this operation may get stuck (?) if nothing is received from the server
+        // Read the output, giving up after the read timeout
         let mut buffer = String::with_capacity(CMD_READ_BUFFER_SIZE);
-        self.stream.read_line(&mut buffer)?;
+        if stream.read_line(&mut buffer)? == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Connection closed by server before replying",
+            ));
+        }
 
         // Return the read string
         Ok(buffer)
@@ -139,13 +527,345 @@ impl Client {
 impl Drop for Client {
     /// Nicely drop the connection when the client is disconnected.
     fn drop(&mut self) {
-        let _ = self.write_command(b"\nQUIT", true);
+        match &mut self.stream {
+            Stream::Tcp(_) => {
+                let _ = self.write_command(b"\nQUIT", true);
+                let _ = self.flush();
+            }
+            Stream::Udp(writer) => {
+                let _ = writer.flush();
+            }
+        }
+    }
+}
+
+/// A writer packing pixelflut commands into UDP datagrams.
+///
+/// Commands are never split across datagrams, a datagram is sent as soon as
+/// the next command doesn't fit within the MTU anymore.
+struct DatagramWriter {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+    mtu: usize,
+}
+
+impl DatagramWriter {
+    /// Create a new datagram writer on the given connected socket.
+    fn new(socket: UdpSocket, mtu: usize) -> DatagramWriter {
+        DatagramWriter {
+            socket,
+            buffer: Vec::with_capacity(mtu),
+            mtu,
+        }
+    }
+
+    /// Append the given command to the current datagram.
+    ///
+    /// Returns whether the previous datagram was sent to make room.
+    fn write_command(&mut self, cmd: &[u8], newline: bool) -> Result<bool, Error> {
+        // Send the current datagram if this command doesn't fit anymore
+        let len = cmd.len() + newline as usize;
+        let sent = self.buffer.len() + len > self.mtu && !self.buffer.is_empty();
+        if sent {
+            self.flush()?;
+        }
+
+        self.buffer.extend_from_slice(cmd);
+        if newline {
+            self.buffer.push(b'\n');
+        }
+        Ok(sent)
+    }
+
+    /// Send the current datagram, if there is anything to send.
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.buffer.is_empty() {
+            self.socket.send(&self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Strip the transport prefix from the given host.
+///
+/// Returns the bare host, and whether to use UDP.
+fn parse_host(host: &str, udp: bool) -> (&str, bool) {
+    if let Some(host) = host.strip_prefix(UDP_HOST_PREFIX) {
+        (host, true)
+    } else if let Some(host) = host.strip_prefix(TCP_HOST_PREFIX) {
+        (host, false)
+    } else {
+        (host, udp)
     }
 }
 
 /// Create a stream to talk to the pixelflut server.
 ///
 /// The stream is returned as result.
-fn create_stream(host: String) -> Result<TcpStream, Error> {
-    TcpStream::connect(host)
+///
+/// Each address the host resolves to is tried, until a connection succeeds.
+fn create_stream(host: &str, timeouts: &Timeouts) -> Result<TcpStream, Error> {
+    let stream = match timeouts.connect {
+        Some(timeout) => {
+            let mut result = Err(Error::new(ErrorKind::NotFound, "Failed to resolve host"));
+            for addr in host.to_socket_addrs()? {
+                result = TcpStream::connect_timeout(&addr, timeout);
+                if result.is_ok() {
+                    break;
+                }
+            }
+            result?
+        }
+        None => TcpStream::connect(host)?,
+    };
+
+    stream.set_read_timeout(timeouts.read)?;
+    stream.set_write_timeout(timeouts.write)?;
+    Ok(stream)
+}
+
+/// Create a UDP socket connected to the pixelflut server.
+fn create_udp_socket(host: &str, timeouts: &Timeouts) -> Result<UdpSocket, Error> {
+    let addr = host
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Failed to resolve host"))?;
+
+    // Bind to any local address of the same family
+    let local: SocketAddr = match addr {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(addr)?;
+    socket.set_write_timeout(timeouts.write)?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::pix::server::Server;
+
+    /// Serve a single connection on a free local port, answering each line with the next reply.
+    ///
+    /// The connection is closed once out of replies. Returns the address to connect to.
+    fn reply_with(replies: &[&str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let replies: Vec<String> = replies.iter().map(|reply| reply.to_string()).collect();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            for reply in replies {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+
+            // Read the request left without reply before closing, so the client sees EOF
+            let _ = reader.read_line(&mut String::new());
+        });
+        host
+    }
+
+    /// Read the pixel at (1, 2) from a server giving the given reply.
+    fn read_pixel(reply: &str) -> Result<Color, Error> {
+        Client::connect(reply_with(&[reply]), &ClientConfig::default())?.read_pixel(1, 2)
+    }
+
+    #[test]
+    fn read_pixel_reply() {
+        let hex = |reply| read_pixel(reply).unwrap().as_hex();
+        assert_eq!(hex("PX 1 2 ff8000\n"), "FF8000FF");
+        assert_eq!(hex("PX 1 2 FF800080\n"), "FF800080");
+        assert_eq!(hex("px  1 2 0a0b0c \r\n"), "0A0B0CFF");
+    }
+
+    #[test]
+    fn read_pixel_other_coordinates() {
+        assert!(read_pixel("PX 2 1 ff8000\n").is_err());
+        assert!(read_pixel("PX 1 20 ff8000\n").is_err());
+    }
+
+    #[test]
+    fn read_pixel_malformed_reply() {
+        for reply in [
+            "PX 1 2 ff80\n",
+            "PX 1 2 ff80000\n",
+            "PX 1 2\n",
+            "SIZE 1 2\n",
+            "\n",
+        ] {
+            assert!(read_pixel(reply).is_err(), "{:?}", reply);
+        }
+    }
+
+    /// Write a row of pixels in batches of the preferred size, as a painter does.
+    ///
+    /// The given function is called after writing each batch.
+    fn write_pixels(client: &mut Client, pixels: u16, mut written: impl FnMut()) {
+        let mut batch = Vec::new();
+        let mut commands = 0;
+        for x in 0..pixels {
+            if batch.len() + client.max_command_size() > client.batch_size() {
+                client.write_batch(&batch, commands).unwrap();
+                written();
+                batch.clear();
+                commands = 0;
+            }
+            client.encode_pixel(&mut batch, x, 0, Color::from(1, 2, 3, 255));
+            commands += 1;
+        }
+        client.write_batch(&batch, commands).unwrap();
+    }
+
+    /// Write pixels in batches of the preferred size, returning the number of flushes.
+    fn count_flushes(config: ClientConfig, pixels: u16) -> u64 {
+        let mut client = Client::connect(Server::spawn((pixels, 1)), &config).unwrap();
+        let stats = Arc::new(Stats::default());
+        client.set_stats(stats.clone());
+        write_pixels(&mut client, pixels, || {});
+        stats.snapshot().flushes
+    }
+
+    /// Write pixels over UDP with the given MTU, returning the received datagrams.
+    fn receive_datagrams(binary: bool, mtu: usize, pixels: u16) -> Vec<Vec<u8>> {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(receiver.local_addr().unwrap()).unwrap();
+
+        // Receive after each batch, so the socket buffer never drops datagrams
+        let mut datagrams = Vec::new();
+        let mut receive = || {
+            let mut buffer = [0; 2048];
+            while let Ok(len) = receiver.recv(&mut buffer) {
+                datagrams.push(buffer[..len].to_vec());
+            }
+        };
+
+        // Dropping the client sends the last datagram
+        let mut client = Client::new_udp(socket, binary, mtu);
+        write_pixels(&mut client, pixels, &mut receive);
+        drop(client);
+        receive();
+        datagrams
+    }
+
+    #[test]
+    fn datagrams_fit_mtu() {
+        for mtu in [MIN_UDP_MTU, 50, 100, DEFAULT_UDP_MTU] {
+            let datagrams = receive_datagrams(false, mtu, 1000);
+            assert!(datagrams.iter().all(|datagram| datagram.len() <= mtu));
+
+            // Each datagram holds whole commands only
+            let mut x = 0;
+            for datagram in datagrams {
+                let text = String::from_utf8(datagram).unwrap();
+                assert!(text.ends_with('\n'));
+                for line in text.lines() {
+                    assert_eq!(line, format!("PX {} 0 010203FF", x));
+                    x += 1;
+                }
+            }
+            assert_eq!(x, 1000);
+        }
+    }
+
+    #[test]
+    fn binary_datagrams_fit_mtu() {
+        let datagrams = receive_datagrams(true, 55, 200);
+        assert!(datagrams.iter().all(|datagram| datagram.len() == 50));
+        let commands = datagrams.concat();
+        assert_eq!(commands.len(), 200 * BINARY_COMMAND_SIZE);
+        for (x, command) in commands.chunks(BINARY_COMMAND_SIZE).enumerate() {
+            assert_eq!(&command[..2], b"PB");
+            assert_eq!(u16::from_le_bytes([command[2], command[3]]), x as u16);
+        }
+    }
+
+    /// Get a binary client configuration with the given flush policy.
+    fn flush_config(flush: FlushPolicy) -> ClientConfig {
+        ClientConfig {
+            binary: true,
+            flush,
+            ..ClientConfig::default()
+        }
+    }
+
+    #[test]
+    fn flush_after_commands() {
+        let flush = FlushPolicy {
+            commands: Some(10),
+            ..FlushPolicy::default()
+        };
+        assert_eq!(count_flushes(flush_config(flush), 200), 20);
+    }
+
+    #[test]
+    fn flush_after_bytes() {
+        let flush = FlushPolicy {
+            bytes: Some(250),
+            ..FlushPolicy::default()
+        };
+        assert_eq!(count_flushes(flush_config(flush), 200), 8);
+    }
+
+    #[test]
+    fn flush_after_batch() {
+        let flush = FlushPolicy {
+            batch: true,
+            ..FlushPolicy::default()
+        };
+        assert_eq!(count_flushes(flush_config(flush), 2000), 1);
+    }
+
+    #[test]
+    fn batch_fits_buffer() {
+        let config = ClientConfig {
+            buffer_size: 100,
+            ..flush_config(FlushPolicy::default())
+        };
+        let client = Client::connect(Server::spawn((1, 1)), &config).unwrap();
+        assert_eq!(client.batch_size(), 100);
+        assert_eq!(count_flushes(config, 200), 0);
+
+        // A batch always fits a command
+        let config = ClientConfig {
+            buffer_size: 1,
+            ..config
+        };
+        let client = Client::connect(Server::spawn((1, 1)), &config).unwrap();
+        assert_eq!(client.batch_size(), BINARY_COMMAND_SIZE);
+    }
+
+    #[test]
+    fn read_pixel_over_tcp() {
+        let host = format!("udp://{}", Server::spawn((16, 8)));
+        let mut client = Client::connect(host.clone(), &ClientConfig::default()).unwrap();
+        let Err(error) = client.read_pixel(1, 2) else {
+            panic!("read a pixel over UDP");
+        };
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+        // Connecting over TCP ignores the UDP prefix
+        let mut client = Client::connect_tcp(&host, &ClientConfig::default()).unwrap();
+        assert_eq!(client.read_pixel(1, 2).unwrap().as_hex(), "000000FF");
+    }
+
+    #[test]
+    fn read_pixel_eof() {
+        let host = reply_with(&[]);
+        let mut client = Client::connect(host, &ClientConfig::default()).unwrap();
+        let Err(error) = client.read_pixel(1, 2) else {
+            panic!("read a pixel from a closed connection");
+        };
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
 }
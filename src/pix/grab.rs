@@ -0,0 +1,57 @@
+use std::io::Error;
+use std::thread;
+
+use image::{Rgba, RgbaImage};
+
+use crate::layout::Layout;
+use crate::pix::client::{Client, ClientConfig};
+use crate::rect::Rect;
+
+/// Grab a snapshot of an area of the pixelflut panel.
+///
+/// The rows of the area are divided over the given number of connections,
+/// which read their pixels in parallel.
+pub fn grab(
+    host: &str,
+    config: &ClientConfig,
+    area: Rect,
+    connections: usize,
+) -> Result<RgbaImage, Error> {
+    // Determine the rows each connection reads, as horizontal stripes
+    let stripes = Layout::Horizontal.regions((area.w, area.h), connections);
+
+    // Read all row slices in parallel
+    let slices: Vec<Result<Vec<Rgba<u8>>, Error>> = thread::scope(|scope| {
+        let threads: Vec<_> = stripes
+            .iter()
+            .map(|stripe| {
+                let start = area.y as usize + stripe[0].y as usize;
+                let end = start + stripe[0].h as usize;
+                scope.spawn(move || {
+                    let mut client = Client::connect_tcp(host, config)?;
+                    let mut pixels = Vec::with_capacity((end - start) * area.w as usize);
+                    for y in start..end {
+                        for x in area.x..area.x + area.w {
+                            let color = client.read_pixel(x, y as u16)?;
+                            pixels.push(Rgba([color.r, color.g, color.b, color.a]));
+                        }
+                    }
+                    Ok(pixels)
+                })
+            })
+            .collect();
+
+        threads
+            .into_iter()
+            .map(|thread| thread.join().expect("grab thread panicked"))
+            .collect()
+    });
+
+    // Assemble the image from all slices
+    let mut image = RgbaImage::new(area.w as u32, area.h as u32);
+    let pixels = slices.into_iter().collect::<Result<Vec<_>, _>>()?;
+    for (pixel, color) in image.pixels_mut().zip(pixels.into_iter().flatten()) {
+        *pixel = color;
+    }
+    Ok(image)
+}
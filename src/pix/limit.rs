@@ -0,0 +1,106 @@
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+// The burst a rate limiter allows, as the time worth of tokens it may save up.
+const BURST: Duration = Duration::from_millis(100);
+
+/// The unit a rate limit is expressed in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RateUnit {
+    /// Pixels per second.
+    Pixels,
+
+    /// Bytes per second.
+    Bytes,
+}
+
+/// Configuration for rate limiting.
+#[derive(Copy, Clone)]
+pub struct RateLimitConfig {
+    /// The rate limit over all connections, if any.
+    pub global: Option<u64>,
+
+    /// The rate limit for each connection, if any.
+    pub connection: Option<u64>,
+
+    /// The unit of the rate limits.
+    pub unit: RateUnit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            global: None,
+            connection: None,
+            unit: RateUnit::Pixels,
+        }
+    }
+}
+
+/// A token bucket rate limiter, which may be shared by many threads.
+///
+/// Tokens are reserved up front, and a thread that takes more tokens than are
+/// available sleeps off its debt. Reservations are made in order, so bandwidth
+/// is shared fairly between threads.
+pub struct RateLimiter {
+    /// The number of tokens added per second.
+    rate: f64,
+
+    /// The maximum number of tokens that may be saved up.
+    burst: f64,
+
+    /// The unit of tokens.
+    unit: RateUnit,
+
+    /// The available tokens, negative when in debt, and when they were last updated.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter allowing the given rate, in the given unit per second.
+    pub fn new(rate: u64, unit: RateUnit) -> RateLimiter {
+        let rate = rate.max(1) as f64;
+        let burst = (rate * BURST.as_secs_f64()).max(1.0);
+        RateLimiter {
+            rate,
+            burst,
+            unit,
+            bucket: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// The maximum size of a batch of commands of the given size, to not exceed the burst.
+    pub fn max_batch_size(&self, command_size: usize) -> usize {
+        let burst = self.burst as usize;
+        match self.unit {
+            RateUnit::Pixels => burst * command_size,
+            RateUnit::Bytes => burst.max(command_size),
+        }
+    }
+
+    /// Take tokens for sending the given number of pixels taking the given number
+    /// of bytes, and wait until the rate allows sending them.
+    pub fn acquire(&self, pixels: u64, bytes: u64) {
+        let amount = match self.unit {
+            RateUnit::Pixels => pixels,
+            RateUnit::Bytes => bytes,
+        } as f64;
+
+        // Refill the bucket, and reserve the tokens
+        let debt = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let tokens = (bucket.0 + (now - bucket.1).as_secs_f64() * self.rate).min(self.burst);
+            *bucket = (tokens - amount, now);
+            amount - tokens
+        };
+
+        // Wait until the reserved tokens would have been added
+        if debt > 0.0 {
+            sleep(Duration::from_secs_f64(debt / self.rate));
+        }
+    }
+}
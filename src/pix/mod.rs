@@ -1,3 +1,7 @@
 // Reexport modules
 pub mod canvas;
 pub mod client;
+pub mod grab;
+pub mod limit;
+pub mod reconnect;
+pub mod server;
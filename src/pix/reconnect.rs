@@ -0,0 +1,153 @@
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::painter::order::splitmix64;
+
+// The maximum time to wait before checking whether waiting should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The policy for reconnecting to a pixelflut server.
+#[derive(Copy, Clone)]
+pub struct ReconnectConfig {
+    /// The delay before the first retry, doubled on every following failure.
+    pub delay: Duration,
+
+    /// The maximum delay between retries.
+    pub max_delay: Duration,
+
+    /// The number of consecutive failed retries to give up after, unlimited if `None`.
+    pub max_retries: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> ReconnectConfig {
+        ReconnectConfig {
+            delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_retries: None,
+        }
+    }
+}
+
+/// A circuit breaker shared by all connections to a server.
+///
+/// When a connection fails the breaker opens, and no connection may be made
+/// until the backoff delay has passed. Then a single connection is attempted as
+/// probe. If it succeeds the breaker closes and everyone may connect again,
+/// if it fails the delay is doubled. This prevents all painters from hammering
+/// a server that is down with simultaneous reconnects.
+pub struct CircuitBreaker {
+    config: ReconnectConfig,
+    state: Mutex<State>,
+
+    /// Notified when the breaker closes, or a probe failed.
+    changed: Condvar,
+}
+
+/// The state of a circuit breaker.
+struct State {
+    /// The number of consecutive failures, the breaker is closed if zero.
+    failures: u32,
+
+    /// When the next probe may be attempted, if open.
+    retry_at: Instant,
+
+    /// Whether a probe is being attempted.
+    probing: bool,
+
+    /// The state of the random generator used for jitter.
+    rng: u64,
+}
+
+impl CircuitBreaker {
+    /// Create a closed circuit breaker with the given reconnect policy.
+    pub fn new(config: ReconnectConfig) -> CircuitBreaker {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        CircuitBreaker {
+            config,
+            state: Mutex::new(State {
+                failures: 0,
+                retry_at: Instant::now(),
+                probing: false,
+                rng: seed,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Wait until a connection may be attempted.
+    ///
+    /// The `stopped` function is polled while waiting, `false` is returned if
+    /// it returns `true`.
+    pub fn acquire(&self, stopped: &mut dyn FnMut() -> bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if stopped() {
+                return false;
+            }
+
+            // Connect freely if closed, a single probe may connect once the delay passed
+            let now = Instant::now();
+            if state.failures == 0 {
+                return true;
+            }
+            if !state.probing && now >= state.retry_at {
+                state.probing = true;
+                return true;
+            }
+
+            let timeout = state.retry_at.saturating_duration_since(now);
+            state = self
+                .changed
+                .wait_timeout(
+                    state,
+                    timeout.clamp(Duration::from_millis(1), POLL_INTERVAL),
+                )
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Report a connection that proved to be usable, closing the breaker.
+    pub fn success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.probing = false;
+        self.changed.notify_all();
+    }
+
+    /// Report a failed or dropped connection, opening the breaker.
+    ///
+    /// Failures of connections attempted before the breaker opened are ignored,
+    /// as they are caused by the same problem.
+    /// Returns `false` if the maximum number of retries has been exceeded.
+    pub fn failure(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.failures > 0 && !state.probing {
+            return true;
+        }
+        state.failures += 1;
+        state.probing = false;
+
+        // Wait twice as long after each failure, with jitter to spread out retries
+        let delay = self
+            .config
+            .delay
+            .saturating_mul(1 << (state.failures - 1).min(16))
+            .min(self.config.max_delay);
+        let jitter = splitmix64(&mut state.rng) % (delay.as_millis() as u64 / 2 + 1);
+        state.retry_at = Instant::now() + delay / 2 + Duration::from_millis(jitter);
+        self.changed.notify_all();
+
+        self.config
+            .max_retries
+            .is_none_or(|max_retries| state.failures <= max_retries)
+    }
+
+    /// Get the number of consecutive failures.
+    pub fn failures(&self) -> u32 {
+        self.state.lock().unwrap().failures
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::{BufWriter, Error};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::color::Color;
+
+// The size of the buffer commands are read into.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+// The length of a binary `PB` command in bytes.
+const PB_COMMAND_SIZE: usize = 10;
+
+// The interval to report throughput statistics at.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A minimal pixelflut server.
+///
+/// This server keeps an in-memory framebuffer, and reports how many pixels
+/// per second are received on stdout. It is intended to benchmark clients locally.
+/// Other messages are logged.
+pub struct Server {
+    /// The framebuffer, with a packed RGBA pixel per entry.
+    pixels: Vec<AtomicU32>,
+
+    /// The size of the framebuffer.
+    size: (u16, u16),
+
+    /// Statistics of all active connections.
+    connections: Mutex<Vec<Weak<ConnectionStats>>>,
+}
+
+/// Statistics of a single connection.
+struct ConnectionStats {
+    /// The connection identifier.
+    id: usize,
+
+    /// The address of the client.
+    peer: SocketAddr,
+
+    /// The number of pixels written on this connection.
+    pixels: AtomicU64,
+}
+
+impl Server {
+    /// Create a new server with a black framebuffer of the given size.
+    pub fn new(size: (u16, u16)) -> Server {
+        Server {
+            pixels: (0..size.0 as usize * size.1 as usize)
+                .map(|_| AtomicU32::new(0x000000FF))
+                .collect(),
+            size,
+            connections: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Listen on the given host, and serve clients until the process is stopped.
+    pub fn serve(self, host: &str) -> Result<(), Error> {
+        let listener = TcpListener::bind(host)?;
+        info!(
+            "Serving a {}x{} canvas on {}...",
+            self.size.0,
+            self.size.1,
+            listener.local_addr()?,
+        );
+        self.run(listener)
+    }
+
+    /// Serve clients on the given listener, until the process is stopped.
+    pub fn run(self, listener: TcpListener) -> Result<(), Error> {
+        // Periodically report statistics
+        let server = Arc::new(self);
+        {
+            let server = server.clone();
+            thread::spawn(move || server.report());
+        }
+
+        // Handle each client on its own thread
+        for (id, stream) in listener.incoming().enumerate() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.handle(id + 1, stream) {
+                    warn!("Connection #{} error: {}", id + 1, e);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Handle a single client connection.
+    fn handle(&self, id: usize, stream: TcpStream) -> Result<(), Error> {
+        let stats = Arc::new(ConnectionStats {
+            id,
+            peer: stream.peer_addr()?,
+            pixels: AtomicU64::new(0),
+        });
+        self.connections
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&stats));
+        info!("Connection #{} opened from {}", id, stats.peer);
+
+        let start = Instant::now();
+        let result = self.process(&stats, stream);
+
+        // Report the connection throughput
+        let pixels = stats.pixels.load(Ordering::Relaxed);
+        let elapsed = start.elapsed().as_secs_f64();
+        info!(
+            "Connection #{} closed: {} pixels in {:.1}s ({:.0} pixels/s)",
+            id,
+            pixels,
+            elapsed,
+            pixels as f64 / elapsed,
+        );
+
+        result
+    }
+
+    /// Process all commands received on the given stream.
+    fn process(&self, stats: &ConnectionStats, mut stream: TcpStream) -> Result<(), Error> {
+        let mut writer = BufWriter::new(stream.try_clone()?);
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        let mut filled = 0;
+
+        loop {
+            // Send replies before we may block on reading
+            writer.flush()?;
+
+            let read = stream.read(&mut buffer[filled..])?;
+            if read == 0 {
+                return Ok(());
+            }
+            filled += read;
+
+            // Process all complete commands in the buffer
+            let mut pos = 0;
+            while pos < filled {
+                let data = &buffer[pos..filled];
+
+                // Binary pixel command
+                if data.starts_with(b"PB") {
+                    if data.len() < PB_COMMAND_SIZE {
+                        break;
+                    }
+                    let x = u16::from_le_bytes([data[2], data[3]]);
+                    let y = u16::from_le_bytes([data[4], data[5]]);
+                    self.set_pixel(x, y, Color::from(data[6], data[7], data[8], data[9]));
+                    stats.pixels.fetch_add(1, Ordering::Relaxed);
+                    pos += PB_COMMAND_SIZE;
+                    continue;
+                }
+
+                // Text commands, wait for a complete line
+                let len = match data.iter().position(|&b| b == b'\n') {
+                    Some(len) => len,
+                    None if filled - pos == buffer.len() => {
+                        return Err(Error::other("Received command that is too long"))
+                    }
+                    None => break,
+                };
+                pos += len + 1;
+
+                let line = String::from_utf8_lossy(&data[..len]);
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some("SIZE"), None, ..) => {
+                        writeln!(writer, "SIZE {} {}", self.size.0, self.size.1)?;
+                    }
+                    (Some("PX"), Some(x), Some(y), color) => {
+                        let (Ok(x), Ok(y)) = (x.parse(), y.parse()) else {
+                            continue;
+                        };
+                        match color {
+                            Some(color) => {
+                                if let Some(color) = Color::from_hex(color) {
+                                    self.set_pixel(x, y, color);
+                                    stats.pixels.fetch_add(1, Ordering::Relaxed);
+                                }
+                            }
+                            None => {
+                                if let Some(color) = self.pixel(x, y) {
+                                    writeln!(writer, "PX {} {} {}", x, y, color.as_rgb_hex())?;
+                                }
+                            }
+                        }
+                    }
+                    (Some("QUIT"), None, ..) => {
+                        writer.flush()?;
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+            }
+
+            // Move the incomplete command to the start of the buffer
+            buffer.copy_within(pos..filled, 0);
+            filled -= pos;
+        }
+    }
+
+    /// Get the index of the given pixel in the framebuffer, if it is on the canvas.
+    fn index(&self, x: u16, y: u16) -> Option<usize> {
+        if x < self.size.0 && y < self.size.1 {
+            Some(y as usize * self.size.0 as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Get the color of the given pixel.
+    fn pixel(&self, x: u16, y: u16) -> Option<Color> {
+        let [r, g, b, a] = self.pixels[self.index(x, y)?]
+            .load(Ordering::Relaxed)
+            .to_be_bytes();
+        Some(Color::from(r, g, b, a))
+    }
+
+    /// Set the given pixel, blending it with the current pixel for translucent colors.
+    fn set_pixel(&self, x: u16, y: u16, color: Color) {
+        let Some(index) = self.index(x, y) else {
+            return;
+        };
+
+        let color = match color.a {
+            0 => return,
+            255 => color,
+            _ => self.pixel(x, y).unwrap().blend(color),
+        };
+        self.pixels[index].store(
+            u32::from_be_bytes([color.r, color.g, color.b, 255]),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Report the throughput of all connections, forever.
+    fn report(&self) {
+        let mut last: HashMap<usize, u64> = HashMap::new();
+        loop {
+            sleep(REPORT_INTERVAL);
+
+            // Collect the active connections
+            let connections: Vec<Arc<ConnectionStats>> = {
+                let mut connections = self.connections.lock().unwrap();
+                connections.retain(|stats| stats.strong_count() > 0);
+                connections.iter().filter_map(Weak::upgrade).collect()
+            };
+            if connections.is_empty() {
+                last.clear();
+                continue;
+            }
+
+            // Report the throughput per connection, and in total
+            let seconds = REPORT_INTERVAL.as_secs_f64();
+            let mut total = 0;
+            let mut next = HashMap::with_capacity(connections.len());
+            for stats in &connections {
+                let pixels = stats.pixels.load(Ordering::Relaxed);
+                let delta = pixels - last.get(&stats.id).copied().unwrap_or(0);
+                total += delta;
+                next.insert(stats.id, pixels);
+                println!(
+                    "  #{} {}: {:.0} pixels/s",
+                    stats.id,
+                    stats.peer,
+                    delta as f64 / seconds,
+                );
+            }
+            println!(
+                "Total: {:.0} pixels/s over {} connection(s)",
+                total as f64 / seconds,
+                connections.len(),
+            );
+            last = next;
+        }
+    }
+}
+
+#[cfg(test)]
+impl Server {
+    /// Serve a canvas of the given size on a free local port in the background, for tests.
+    ///
+    /// Returns the address to connect to.
+    pub fn spawn(size: (u16, u16)) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || Server::new(size).run(listener));
+        host
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+    use crate::pix::client::{Client, ClientConfig};
+
+    /// A raw connection to a server, to send commands and read replies.
+    struct Connection {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Connection {
+        /// Connect to the server at the given host.
+        fn open(host: &str) -> Connection {
+            let stream = TcpStream::connect(host).unwrap();
+            stream.set_nodelay(true).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            Connection {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                stream,
+            }
+        }
+
+        /// Send the given data, without waiting for a reply.
+        fn send(&mut self, data: &[u8]) {
+            self.stream.write_all(data).unwrap();
+        }
+
+        /// Send the given data, and read a reply.
+        fn request(&mut self, data: &[u8]) -> String {
+            self.send(data);
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line
+        }
+    }
+
+    #[test]
+    fn size() {
+        let mut connection = Connection::open(&Server::spawn((16, 8)));
+        assert_eq!(connection.request(b"SIZE\n"), "SIZE 16 8\n");
+    }
+
+    #[test]
+    fn write_and_read_pixels() {
+        let mut connection = Connection::open(&Server::spawn((16, 8)));
+        assert_eq!(connection.request(b"PX 0 0\n"), "PX 0 0 000000\n");
+        assert_eq!(
+            connection.request(b"PX 3 4 ff8000\nPX 15 7 0a0b0cff\nPX 3 4\n"),
+            "PX 3 4 ff8000\n"
+        );
+        assert_eq!(connection.request(b"PX 15 7\n"), "PX 15 7 0a0b0c\n");
+    }
+
+    #[test]
+    fn blend_translucent_pixels() {
+        let mut connection = Connection::open(&Server::spawn((16, 8)));
+        connection.send(b"PX 1 1 ffffff\nPX 1 1 00000080\nPX 2 2 ff000000\n");
+        assert_eq!(connection.request(b"PX 1 1\n"), "PX 1 1 7f7f7f\n");
+        assert_eq!(connection.request(b"PX 2 2\n"), "PX 2 2 000000\n");
+    }
+
+    #[test]
+    fn ignore_pixels_off_canvas() {
+        let mut connection = Connection::open(&Server::spawn((16, 8)));
+        assert_eq!(
+            connection.request(b"PX 16 0 ffffff\nPX 99 99\nPX a b\nSIZE\n"),
+            "SIZE 16 8\n"
+        );
+    }
+
+    #[test]
+    fn binary_pixels() {
+        let mut connection = Connection::open(&Server::spawn((16, 8)));
+        connection.send(&[b'P', b'B', 9, 0, 5, 0, 1, 2, 3, 255]);
+        assert_eq!(connection.request(b"PX 9 5\n"), "PX 9 5 010203\n");
+    }
+
+    #[test]
+    fn buffer_split_commands() {
+        let mut connection = Connection::open(&Server::spawn((16, 8)));
+        let parts: [&[u8]; 5] = [b"PX 5 ", b"6 00ff00\nPB", &[1, 0, 2], &[0, 9, 9, 9], &[255]];
+        for part in parts {
+            connection.send(part);
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(connection.request(b"PX 5 6\n"), "PX 5 6 00ff00\n");
+        assert_eq!(connection.request(b"PX 1 2\n"), "PX 1 2 090909\n");
+    }
+
+    #[test]
+    fn quit() {
+        let mut connection = Connection::open(&Server::spawn((16, 8)));
+        assert_eq!(connection.request(b"QUIT\n"), "");
+    }
+
+    #[test]
+    fn client_reads_screen_size() {
+        let host = Server::spawn((640, 480));
+        let mut client = Client::connect(host, &ClientConfig::default()).unwrap();
+        assert_eq!(client.read_screen_size().unwrap(), (640, 480));
+    }
+
+    #[test]
+    fn client_writes_pixels() {
+        for binary in [false, true] {
+            let config = ClientConfig {
+                binary,
+                ..ClientConfig::default()
+            };
+            let mut client = Client::connect(Server::spawn((16, 8)), &config).unwrap();
+            let mut batch = Vec::new();
+            client.encode_pixel(&mut batch, 2, 3, Color::from(10, 20, 30, 255));
+            client.encode_pixel(&mut batch, 15, 7, Color::from(255, 0, 0, 255));
+            client.write_batch(&batch, 2).unwrap();
+
+            assert_eq!(client.read_pixel(2, 3).unwrap().as_hex(), "0A141EFF");
+            assert_eq!(client.read_pixel(15, 7).unwrap().as_hex(), "FF0000FF");
+            assert_eq!(client.read_pixel(0, 0).unwrap().as_hex(), "000000FF");
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::str::FromStr;
+
+use clap::ValueEnum;
+
+/// The order to play the frames of an animation in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Playback {
+    /// From the first to the last frame.
+    Forward,
+
+    /// From the last to the first frame.
+    Reverse,
+
+    /// Forward, and back again.
+    PingPong,
+}
+
+/// What to do once all animations finished playing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnEnd {
+    /// Keep painting the last frame.
+    Hold,
+
+    /// Stop painting, and quit.
+    Exit,
+}
+
+/// A range of frames to play, as `START-END`, counting from 0 with both ends included.
+///
+/// Either end may be omitted, `5-` plays from the sixth frame to the last one.
+/// A single number selects just that frame.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameRange {
+    /// The first frame.
+    start: usize,
+
+    /// The last frame, the last frame of the animation if not set.
+    end: Option<usize>,
+}
+
+impl FrameRange {
+    /// Select the frames within this range.
+    pub fn select<T>(self, frames: Vec<T>) -> Vec<T> {
+        let end = self.end.map_or(frames.len(), |end| end + 1);
+        frames.into_iter().take(end).skip(self.start).collect()
+    }
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(range: &str) -> Result<FrameRange, String> {
+        let invalid = || format!("invalid frame range '{}', expected START-END", range);
+        let parse = |frame: &str| frame.trim().parse::<usize>().map_err(|_| invalid());
+
+        let Some((start, end)) = range.split_once('-') else {
+            let frame = parse(range)?;
+            return Ok(FrameRange {
+                start: frame,
+                end: Some(frame),
+            });
+        };
+        let start = if start.trim().is_empty() {
+            0
+        } else {
+            parse(start)?
+        };
+        let end = if end.trim().is_empty() {
+            None
+        } else {
+            Some(parse(end)?)
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(invalid());
+        }
+        Ok(FrameRange { start, end })
+    }
+}
+
+/// Configuration for playing animations.
+#[derive(Copy, Clone, Debug)]
+pub struct PlaybackConfig {
+    /// The order to play the frames in.
+    pub playback: Playback,
+
+    /// The number of times to play, 0 to play forever.
+    /// Uses the loop count of the image if not set, and plays forever without one.
+    pub loops: Option<u32>,
+
+    /// The frames to play.
+    pub frames: FrameRange,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> PlaybackConfig {
+        PlaybackConfig {
+            playback: Playback::Forward,
+            loops: None,
+            frames: FrameRange::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Select the frames of an animation with the given number of frames, in the given range.
+    fn select(range: &str, frames: usize) -> Vec<usize> {
+        range
+            .parse::<FrameRange>()
+            .unwrap()
+            .select((0..frames).collect())
+    }
+
+    #[test]
+    fn parse_frame_range() {
+        assert_eq!(select("2-4", 10), [2, 3, 4]);
+        assert_eq!(select(" 2 - 4 ", 10), [2, 3, 4]);
+        assert_eq!(select("5-", 8), [5, 6, 7]);
+        assert_eq!(select("-1", 8), [0, 1]);
+        assert_eq!(select("-", 3), [0, 1, 2]);
+        assert_eq!(select("3", 8), [3]);
+        assert_eq!(select("4-4", 8), [4]);
+    }
+
+    #[test]
+    fn select_beyond_last_frame() {
+        assert_eq!(select("2-100", 4), [2, 3]);
+        assert!(select("5-", 4).is_empty());
+        assert!(select("7", 4).is_empty());
+    }
+
+    #[test]
+    fn default_selects_all_frames() {
+        assert_eq!(FrameRange::default().select(vec![1, 2, 3]), [1, 2, 3]);
+    }
+
+    #[test]
+    fn parse_invalid_frame_range() {
+        for range in ["", "a", "1-b", "-x", "4-2", "1-2-3", "-1-", "1.5"] {
+            assert!(range.parse::<FrameRange>().is_err(), "{:?}", range);
+        }
+    }
+}
@@ -12,4 +12,18 @@ impl Rect {
     pub fn from(x: u16, y: u16, w: u16, h: u16) -> Rect {
         Rect { x, y, w, h }
     }
+
+    /// Get the smallest rectangle containing all given rectangles.
+    pub fn bounds(rects: &[Rect]) -> Rect {
+        let x = rects.iter().map(|r| r.x).min().unwrap_or(0);
+        let y = rects.iter().map(|r| r.y).min().unwrap_or(0);
+        let right = rects.iter().map(|r| r.x + r.w).max().unwrap_or(0);
+        let bottom = rects.iter().map(|r| r.y + r.h).max().unwrap_or(0);
+        Rect::from(x, y, right - x, bottom - y)
+    }
+
+    /// Check whether the given point is inside this rectangle.
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.w && y - self.y < self.h
+    }
 }
@@ -0,0 +1,201 @@
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, RgbaImage};
+
+/// How to scale an image to the size to paint it at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Scale {
+    /// Scale to fit within the size, keeping the aspect ratio, padding with transparency.
+    Fit,
+
+    /// Scale to fill the size, keeping the aspect ratio, cropping what falls outside.
+    Fill,
+
+    /// Stretch to exactly the size.
+    Stretch,
+
+    /// Keep the native size, padding or cropping to the size.
+    None,
+}
+
+/// The filter to resample images with when scaling.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Filter {
+    /// Nearest neighbour, keeping pixel art sharp.
+    Nearest,
+
+    /// Linear.
+    Triangle,
+
+    /// Cubic.
+    CatmullRom,
+
+    /// Gaussian.
+    Gaussian,
+
+    /// Lanczos with window 3, sharp for photos.
+    Lanczos3,
+}
+
+impl Filter {
+    /// Get the matching filter type to resize images with.
+    fn filter_type(self) -> FilterType {
+        match self {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Where to place a scaled image within the size to paint it at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Align {
+    /// In the center.
+    Center,
+
+    /// At the top edge, centered horizontally.
+    Top,
+
+    /// At the bottom edge, centered horizontally.
+    Bottom,
+
+    /// At the left edge, centered vertically.
+    Left,
+
+    /// At the right edge, centered vertically.
+    Right,
+
+    /// In the top left corner.
+    TopLeft,
+
+    /// In the top right corner.
+    TopRight,
+
+    /// In the bottom left corner.
+    BottomLeft,
+
+    /// In the bottom right corner.
+    BottomRight,
+}
+
+impl Align {
+    /// The position along each axis, from `0.0` at the start to `1.0` at the end.
+    fn factors(self) -> (f64, f64) {
+        match self {
+            Align::Center => (0.5, 0.5),
+            Align::Top => (0.5, 0.0),
+            Align::Bottom => (0.5, 1.0),
+            Align::Left => (0.0, 0.5),
+            Align::Right => (1.0, 0.5),
+            Align::TopLeft => (0.0, 0.0),
+            Align::TopRight => (1.0, 0.0),
+            Align::BottomLeft => (0.0, 1.0),
+            Align::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// Complete a partially given size for an image of the given dimensions.
+///
+/// A missing width or height follows the aspect ratio of the image, the native
+/// size is used if neither is given.
+pub fn complete_size(dimensions: (u32, u32), size: (Option<u16>, Option<u16>)) -> (u32, u32) {
+    let (w, h) = (dimensions.0 as f64, dimensions.1 as f64);
+    match size {
+        (Some(width), Some(height)) => (width as u32, height as u32),
+        (Some(width), None) => (width as u32, ((h * width as f64 / w).round() as u32).max(1)),
+        (None, Some(height)) => (
+            ((w * height as f64 / h).round() as u32).max(1),
+            height as u32,
+        ),
+        (None, None) => dimensions,
+    }
+}
+
+/// Scale an image to the given size.
+///
+/// Unless stretched, the image keeps its aspect ratio and is aligned within the
+/// size. The returned image is always of the given size.
+pub fn scale_image(
+    image: DynamicImage,
+    size: (u32, u32),
+    scale: Scale,
+    align: Align,
+    filter: Filter,
+) -> DynamicImage {
+    if image.dimensions() == size {
+        return image;
+    }
+
+    // Determine the size to scale to
+    let (w, h) = (image.width() as f64, image.height() as f64);
+    let factor = match scale {
+        Scale::Stretch => return resize(image, size, filter),
+        Scale::Fit => (size.0 as f64 / w).min(size.1 as f64 / h),
+        Scale::Fill => (size.0 as f64 / w).max(size.1 as f64 / h),
+        Scale::None => 1.0,
+    };
+    let scaled = (
+        ((w * factor).round() as u32).max(1),
+        ((h * factor).round() as u32).max(1),
+    );
+    let image = if scaled == image.dimensions() {
+        image
+    } else {
+        resize(image, scaled, filter)
+    };
+
+    // Place the scaled image, padding with transparency and cropping what falls outside
+    let (fx, fy) = align.factors();
+    let x = ((size.0 as f64 - scaled.0 as f64) * fx).round() as i64;
+    let y = ((size.1 as f64 - scaled.1 as f64) * fy).round() as i64;
+    let mut placed = RgbaImage::new(size.0, size.1);
+    imageops::replace(&mut placed, &image.to_rgba8(), x, y);
+    DynamicImage::ImageRgba8(placed)
+}
+
+/// Resize an image to exactly the given size.
+///
+/// With the nearest filter, an image scaled up by an exact multiple has each
+/// pixel repeated, so every pixel becomes a sharp block of the same size.
+fn resize(image: DynamicImage, size: (u32, u32), filter: Filter) -> DynamicImage {
+    let (w, h) = image.dimensions();
+    if filter == Filter::Nearest && size.0.is_multiple_of(w) && size.1.is_multiple_of(h) {
+        let (fx, fy) = (size.0 / w, size.1 / h);
+        let image = image.to_rgba8();
+        return DynamicImage::ImageRgba8(RgbaImage::from_fn(size.0, size.1, |x, y| {
+            *image.get_pixel(x / fx, y / fy)
+        }));
+    }
+    image.resize_exact(size.0, size.1, filter.filter_type())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_size_keeps_aspect_ratio() {
+        assert_eq!(complete_size((200, 100), (Some(64), None)), (64, 32));
+        assert_eq!(complete_size((200, 100), (None, Some(64))), (128, 64));
+        assert_eq!(complete_size((3, 2), (Some(10), None)), (10, 7));
+        assert_eq!(complete_size((1000, 1), (Some(10), None)), (10, 1));
+    }
+
+    #[test]
+    fn complete_size_given_or_native() {
+        assert_eq!(complete_size((200, 100), (Some(64), Some(64))), (64, 64));
+        assert_eq!(complete_size((200, 100), (None, None)), (200, 100));
+    }
+
+    #[test]
+    fn scale_to_width_only() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(40, 20));
+        let size = complete_size(image.dimensions(), (Some(10), None));
+        let scaled = scale_image(image, size, Scale::Fit, Align::Center, Filter::Nearest);
+        assert_eq!(scaled.dimensions(), (10, 5));
+    }
+}
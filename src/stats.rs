@@ -0,0 +1,202 @@
+use std::ops::Sub;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Statistics of a single painter.
+///
+/// Counters are updated by the painter thread, and may be read from any thread.
+#[derive(Default)]
+pub struct Stats {
+    /// The number of pixels sent.
+    pixels: AtomicU64,
+
+    /// The number of bytes sent.
+    bytes: AtomicU64,
+
+    /// The number of times the connection was flushed.
+    flushes: AtomicU64,
+
+    /// The number of errors the painter ran into.
+    errors: AtomicU64,
+
+    /// The number of times the painter reconnected.
+    reconnects: AtomicU64,
+
+    /// The number of frames replaced before the painter painted them completely.
+    dropped: AtomicU64,
+}
+
+impl Stats {
+    /// Count sent pixels, and the number of bytes they took.
+    pub fn add_pixels(&self, pixels: u64, bytes: u64) {
+        self.pixels.fetch_add(pixels, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Count a flush of the connection.
+    pub fn add_flush(&self) {
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an error.
+    pub fn add_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a reconnect.
+    pub fn add_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count frames replaced before being painted completely.
+    pub fn add_dropped(&self, frames: u64) {
+        self.dropped.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    /// Take a snapshot of all counters.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pixels: self.pixels.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            flushes: self.flushes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The counters of one or more painters at some point in time.
+#[derive(Copy, Clone, Default)]
+pub struct Snapshot {
+    /// The number of pixels sent.
+    pub pixels: u64,
+
+    /// The number of bytes sent.
+    pub bytes: u64,
+
+    /// The number of times the connection was flushed.
+    pub flushes: u64,
+
+    /// The number of errors.
+    pub errors: u64,
+
+    /// The number of reconnects.
+    pub reconnects: u64,
+
+    /// The number of dropped frames.
+    pub dropped: u64,
+}
+
+impl Snapshot {
+    /// Sum the counters of all given painters.
+    pub fn total(snapshots: &[Snapshot]) -> Snapshot {
+        snapshots
+            .iter()
+            .fold(Snapshot::default(), |total, s| Snapshot {
+                pixels: total.pixels + s.pixels,
+                bytes: total.bytes + s.bytes,
+                flushes: total.flushes + s.flushes,
+                errors: total.errors + s.errors,
+                reconnects: total.reconnects + s.reconnects,
+                dropped: total.dropped + s.dropped,
+            })
+    }
+
+    /// Format as a JSON object, with rates over the given number of seconds.
+    fn to_json(self, secs: f64) -> String {
+        format!(
+            "{{\"pixels_per_sec\":{:.0},\"mbit_per_sec\":{:.3},\"pixels\":{},\"bytes\":{},\"flushes\":{},\"errors\":{},\"reconnects\":{},\"dropped_frames\":{}}}",
+            self.pixels as f64 / secs,
+            mbit(self.bytes) / secs,
+            self.pixels,
+            self.bytes,
+            self.flushes,
+            self.errors,
+            self.reconnects,
+            self.dropped,
+        )
+    }
+}
+
+impl Sub for Snapshot {
+    type Output = Snapshot;
+
+    fn sub(self, other: Snapshot) -> Snapshot {
+        Snapshot {
+            pixels: self.pixels - other.pixels,
+            bytes: self.bytes - other.bytes,
+            flushes: self.flushes - other.flushes,
+            errors: self.errors - other.errors,
+            reconnects: self.reconnects - other.reconnects,
+            dropped: self.dropped - other.dropped,
+        }
+    }
+}
+
+/// Periodically report the throughput of all painters, until stopped.
+///
+/// Reports are printed as text, or as JSON lines if `json` is set.
+/// Reporting stops when a stop signal is received, or the channel is closed.
+pub fn report(stats: &[Arc<Stats>], interval: Duration, json: bool, stop: &Receiver<()>) {
+    let started = Instant::now();
+    let mut previous: Vec<Snapshot> = stats.iter().map(|stats| stats.snapshot()).collect();
+    let mut last = started;
+
+    loop {
+        // Wait for the next report, or until stopped
+        match stop.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        // Determine what happened since the last report
+        let now = Instant::now();
+        let secs = (now - last).as_secs_f64();
+        let current: Vec<Snapshot> = stats.iter().map(|stats| stats.snapshot()).collect();
+        let deltas: Vec<Snapshot> = current
+            .iter()
+            .zip(&previous)
+            .map(|(&current, &previous)| current - previous)
+            .collect();
+        let total = Snapshot::total(&deltas);
+
+        if json {
+            let painters: Vec<String> = deltas.iter().map(|delta| delta.to_json(secs)).collect();
+            println!(
+                "{{\"time\":{:.3},\"total\":{},\"painters\":[{}]}}",
+                (now - started).as_secs_f64(),
+                total.to_json(secs),
+                painters.join(","),
+            );
+        } else {
+            println!(
+                "Throughput: {:.0} pixels/s, {:.2} Mbit/s, {:.0} flushes/s, {} error(s), {} reconnect(s), {} dropped frame(s)",
+                total.pixels as f64 / secs,
+                mbit(total.bytes) / secs,
+                total.flushes as f64 / secs,
+                total.errors,
+                total.reconnects,
+                total.dropped,
+            );
+            for (i, delta) in deltas.iter().enumerate() {
+                println!(
+                    "  #{}: {:.0} pixels/s, {:.2} Mbit/s",
+                    i,
+                    delta.pixels as f64 / secs,
+                    mbit(delta.bytes) / secs,
+                );
+            }
+        }
+
+        previous = current;
+        last = now;
+    }
+}
+
+/// Convert a number of bytes into megabits.
+fn mbit(bytes: u64) -> f64 {
+    bytes as f64 * 8.0 / 1_000_000.0
+}